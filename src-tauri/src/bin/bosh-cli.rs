@@ -0,0 +1,284 @@
+//! Command line tools for working with tracks without opening the app.
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::process;
+
+use anyhow::{anyhow, Context, Result};
+use bosh::frame_dump::{DumpFormat, FrameWriter};
+use bosh::serialization::{ConversionWarning, TrackFormat};
+use bosh::{track_loading, track_saving};
+use bosh_rs::Track;
+use clap::{Args, Parser, Subcommand};
+
+/// Every track converted without losing anything.
+const EXIT_OK: i32 = 0;
+/// At least one track could not be converted at all.
+const EXIT_FAILED: i32 = 1;
+/// Every track was converted, but at least one lost information on the way.
+const EXIT_LOSSY: i32 = 2;
+
+#[derive(Parser)]
+#[clap(
+    name = "bosh-cli",
+    about = "Works with bosh tracks from the command line"
+)]
+struct Cli {
+    #[clap(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Converts tracks to another format.
+    ///
+    /// Exits with 1 if any track failed to convert, or 2 if any track lost
+    /// information that the new format cannot represent.
+    Convert(ConvertArgs),
+    /// Simulates a track and writes where every rider point is on each frame.
+    Simulate(SimulateArgs),
+}
+
+#[derive(Args)]
+struct ConvertArgs {
+    /// Track to convert, or a glob pattern like `archive/*.trk`.
+    input: String,
+    /// File to write to, or a directory when the input matches several tracks.
+    output: PathBuf,
+    /// Format to write (boshtf, track.json or trk), instead of guessing it from
+    /// the output's extension.
+    #[clap(long, value_parser = parse_format)]
+    format: Option<TrackFormat>,
+}
+
+#[derive(Args)]
+struct SimulateArgs {
+    /// Track to simulate.
+    track: PathBuf,
+    /// Frames to simulate, like `0..2400`. The last frame is not included.
+    #[clap(long, value_parser = parse_frames, default_value = "0..2400")]
+    frames: Range<usize>,
+    /// File to write the frames to, as csv or jsonl depending on its extension.
+    #[clap(long)]
+    out: PathBuf,
+}
+
+fn parse_frames(frames: &str) -> Result<Range<usize>, String> {
+    let invalid = || format!("invalid frame range {:?}, expected start..end", frames);
+    let (start, end) = frames.split_once("..").ok_or_else(invalid)?;
+    let start = start.trim().parse().map_err(|_| invalid())?;
+    let end = end.trim().parse().map_err(|_| invalid())?;
+    if start > end {
+        return Err(format!("frame range {:?} ends before it starts", frames));
+    }
+
+    Ok(start..end)
+}
+
+fn parse_format(format: &str) -> Result<TrackFormat, String> {
+    TrackFormat::from_extension(format).ok_or_else(|| {
+        format!(
+            "unknown format {:?}, expected boshtf, track.json or trk",
+            format
+        )
+    })
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    let code = match cli.command {
+        Command::Convert(args) => convert(&args),
+        Command::Simulate(args) => simulate(&args).map(|()| EXIT_OK),
+    };
+
+    process::exit(code.unwrap_or_else(|err| {
+        eprintln!("error: {:#}", err);
+        EXIT_FAILED
+    }));
+}
+
+/// Result of converting a single track.
+enum Outcome {
+    Converted,
+    Lossy(Vec<ConversionWarning>),
+    Failed(anyhow::Error),
+}
+
+fn convert(args: &ConvertArgs) -> Result<i32> {
+    // a track that exists is taken as is, even if its name looks like a pattern
+    let is_literal = Path::new(&args.input).exists();
+    let inputs = if is_literal {
+        vec![PathBuf::from(&args.input)]
+    } else {
+        glob::glob(&args.input)
+            .with_context(|| format!("invalid input pattern {}", args.input))?
+            .collect::<Result<Vec<PathBuf>, _>>()
+            .context("error while matching input pattern")?
+    };
+    if inputs.is_empty() {
+        return Err(anyhow!("no tracks match {}", args.input));
+    }
+
+    let is_pattern = !is_literal && args.input.contains(['*', '?', '[']);
+    let into_directory = is_pattern || inputs.len() > 1 || args.output.is_dir();
+    if into_directory {
+        fs::create_dir_all(&args.output)
+            .with_context(|| format!("error while creating directory {}", args.output.display()))?;
+    }
+
+    let (mut converted, mut lossy, mut failed) = (0, 0, 0);
+    // tracks like `a.trk` and `a.track.json` end up with the same output name
+    let mut written = HashSet::new();
+    for input in &inputs {
+        let (output, outcome) = match output_for(args, input, into_directory) {
+            Ok((output, _)) if written.contains(&output) => {
+                let err = anyhow!("another track was already converted to this file");
+                (Some(output), Outcome::Failed(err))
+            }
+            Ok((output, format)) => {
+                let outcome = convert_one(input, &output, format);
+                written.insert(output.clone());
+                (Some(output), outcome)
+            }
+            Err(err) => (None, Outcome::Failed(err)),
+        };
+
+        let arrow = match &output {
+            Some(output) => format!("{} -> {}", input.display(), output.display()),
+            None => input.display().to_string(),
+        };
+        match outcome {
+            Outcome::Converted => {
+                converted += 1;
+                println!("converted {}", arrow);
+            }
+            Outcome::Lossy(warnings) => {
+                lossy += 1;
+                println!("lossy     {} ({} warnings)", arrow, warnings.len());
+                for warning in warnings {
+                    println!("    {}", warning);
+                }
+            }
+            Outcome::Failed(err) => {
+                failed += 1;
+                println!("failed    {}: {:#}", arrow, err);
+            }
+        }
+    }
+
+    println!(
+        "{} converted, {} lossy, {} failed",
+        converted, lossy, failed
+    );
+
+    Ok(if failed > 0 {
+        EXIT_FAILED
+    } else if lossy > 0 {
+        EXIT_LOSSY
+    } else {
+        EXIT_OK
+    })
+}
+
+/// Where a track gets written to, and in which format.
+fn output_for(
+    args: &ConvertArgs,
+    input: &Path,
+    into_directory: bool,
+) -> Result<(PathBuf, TrackFormat)> {
+    if !into_directory {
+        let format = match args.format {
+            Some(format) => format,
+            None => format_of(&args.output).with_context(|| {
+                format!(
+                    "cannot tell the format of {} from its extension, use --format",
+                    args.output.display()
+                )
+            })?,
+        };
+        return Ok((args.output.clone(), format));
+    }
+
+    let format = args
+        .format
+        .context("--format is needed when converting into a directory")?;
+    let name = input
+        .file_name()
+        .and_then(|name| name.to_str())
+        .with_context(|| format!("{} has no usable file name", input.display()))?;
+    let stem = match TrackFormat::from_extension(name) {
+        Some(old) => name.trim_end_matches(old.extension()).trim_end_matches('.'),
+        None => name.rsplit_once('.').map_or(name, |(stem, _)| stem),
+    };
+
+    Ok((
+        args.output.join(format!("{}.{}", stem, format.extension())),
+        format,
+    ))
+}
+
+fn format_of(path: &Path) -> Option<TrackFormat> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(TrackFormat::from_extension)
+}
+
+fn convert_one(input: &Path, output: &Path, format: TrackFormat) -> Outcome {
+    match load_and_save(input, output, format) {
+        Ok(warnings) if warnings.is_empty() => Outcome::Converted,
+        Ok(warnings) => Outcome::Lossy(warnings),
+        Err(err) => Outcome::Failed(err),
+    }
+}
+
+fn load_and_save(
+    input: &Path,
+    output: &Path,
+    format: TrackFormat,
+) -> Result<Vec<ConversionWarning>> {
+    let input = input.to_str().context("input path is not valid unicode")?;
+    let output = output
+        .to_str()
+        .context("output path is not valid unicode")?;
+
+    let loaded = track_loading::load(input)?;
+    track_saving::save(output, format, &loaded.track)
+}
+
+fn simulate(args: &SimulateArgs) -> Result<()> {
+    let format = DumpFormat::from_path(&args.out).with_context(|| {
+        format!(
+            "cannot tell the format of {} from its extension, expected .csv or .jsonl",
+            args.out.display()
+        )
+    })?;
+
+    let input = args
+        .track
+        .to_str()
+        .context("track path is not valid unicode")?;
+    let loaded = track_loading::load(input)?;
+    let track: Track = (&loaded.track).into();
+
+    let file = File::create(&args.out)
+        .with_context(|| format!("error while creating {}", args.out.display()))?;
+    let mut writer = FrameWriter::new(BufWriter::new(file), format)?;
+    for frame in args.frames.clone() {
+        writer.write_frame(frame, &track.entity_positions_at(frame))?;
+    }
+    writer.finish()?;
+
+    println!(
+        "simulated frames {}..{} of {} -> {}",
+        args.frames.start,
+        args.frames.end,
+        args.track.display(),
+        args.out.display()
+    );
+
+    Ok(())
+}
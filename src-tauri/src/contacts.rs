@@ -0,0 +1,237 @@
+//! Which lines the riders are touching on each frame, for debugging tracks and
+//! finding physics lines that are never used.
+//!
+//! bosh-rs does not report its collisions, so they are worked out afterwards
+//! from where each point ended up. This is an approximation: a point that a
+//! bone moved off of the line later in the same frame is not seen as touching
+//! it, and a point that landed exactly on a line without colliding is.
+
+use std::collections::{HashMap, HashSet};
+
+use bosh::frame_dump::point_name;
+use bosh::serialization::boshtf::BoshTFLine;
+use bosh_rs::rider::{Entity, PointIndex};
+use bosh_rs::{Line, LineType, Vector2D};
+use serde::Serialize;
+
+use crate::frame_cache::{HITBOX_MARGIN, MAX_EXTENSION};
+use crate::spatial::LineGrid;
+
+/// How close a point has to be to a line at the end of a frame to be touching
+/// it. Collisions move points onto the line, so this only has to cover rounding.
+pub const CONTACT_DISTANCE: f64 = 1e-6;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PointContact {
+    /// index of the entity in the track
+    pub entity: usize,
+    pub point: PointIndex,
+    /// ids of the lines that the point is touching, in ascending order
+    pub lines: Vec<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameContacts {
+    pub frame: usize,
+    /// only the points that are touching a line
+    pub contacts: Vec<PointContact>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LineContacts {
+    pub frames: Vec<FrameContacts>,
+    /// ids of every line touched in any of the frames, in ascending order
+    pub hit_lines: Vec<u64>,
+}
+
+/// The part of a physics line that points collide with.
+#[derive(Debug, Clone, Copy)]
+struct Surface {
+    start: Vector2D,
+    end: Vector2D,
+    flipped: bool,
+    /// whether each end is extended, so that points slightly past it still collide
+    extended: (bool, bool),
+}
+
+impl Surface {
+    fn from_line(line: &Line) -> Surface {
+        Surface {
+            start: line.ends.0.location,
+            end: line.ends.1.location,
+            flipped: line.flipped,
+            extended: (line.ends.0.extended, line.ends.1.extended),
+        }
+    }
+
+    /// Whether a point that ended a frame at `location` collided with the line,
+    /// the way Line Rider decides it: the point has to be on the line, within its
+    /// ends or their extensions, and has to have come from the side that the
+    /// line is ridden on.
+    fn touches(&self, location: Vector2D, previous_location: Vector2D) -> bool {
+        let delta = (self.end.0 - self.start.0, self.end.1 - self.start.1);
+        let length = (delta.0 * delta.0 + delta.1 * delta.1).sqrt();
+        if length == 0.0 {
+            return false;
+        }
+
+        // points on the positive side of the normal are pushed back onto the line
+        let sign = if self.flipped { -1.0 } else { 1.0 };
+        let normal = (-delta.1 / length * sign, delta.0 / length * sign);
+        let distance = |point: Vector2D| {
+            (point.0 - self.start.0) * normal.0 + (point.1 - self.start.1) * normal.1
+        };
+        let along = ((location.0 - self.start.0) * delta.0 + (location.1 - self.start.1) * delta.1)
+            / (length * length);
+
+        let extension = MAX_EXTENSION.min(HITBOX_MARGIN / length);
+        let limit_left = if self.extended.0 { -extension } else { 0.0 };
+        let limit_right = if self.extended.1 {
+            1.0 + extension
+        } else {
+            1.0
+        };
+
+        distance(location).abs() <= CONTACT_DISTANCE
+            && distance(previous_location) <= CONTACT_DISTANCE
+            && along >= limit_left
+            && along <= limit_right
+    }
+}
+
+/// Finds the physics lines that points are touching. Scenery lines are never
+/// touched, since nothing collides with them.
+pub struct ContactFinder<'a> {
+    grid: &'a LineGrid,
+    physics: HashMap<u64, Surface>,
+}
+
+impl<'a> ContactFinder<'a> {
+    pub fn new(grid: &'a LineGrid, lines: &[BoshTFLine]) -> ContactFinder<'a> {
+        let physics = lines
+            .iter()
+            .filter(|l| !matches!(l.line.line_type, LineType::Scenery))
+            .filter_map(|l| Some((l.id?, Surface::from_line(&l.line))))
+            .collect();
+
+        ContactFinder { grid, physics }
+    }
+
+    /// Ids of the lines that a point is touching, in ascending order.
+    pub fn touching(&self, location: Vector2D, previous_location: Vector2D) -> Vec<u64> {
+        // extensions reach up to HITBOX_MARGIN past the ends of a line
+        let mut lines: Vec<u64> = self
+            .grid
+            .near_point(location, HITBOX_MARGIN + CONTACT_DISTANCE)
+            .into_iter()
+            .filter(|id| {
+                self.physics.get(id).map_or(false, |surface| {
+                    surface.touches(location, previous_location)
+                })
+            })
+            .collect();
+        lines.sort_unstable();
+
+        lines
+    }
+
+    pub fn frame_contacts(&self, frame: usize, entities: &[Entity]) -> FrameContacts {
+        let mut contacts = vec![];
+        for (index, entity) in entities.iter().enumerate() {
+            let start = contacts.len();
+            for (point, state) in &entity.points {
+                let lines = self.touching(state.location, state.previous_location);
+                if !lines.is_empty() {
+                    contacts.push(PointContact {
+                        entity: index,
+                        point: point.clone(),
+                        lines,
+                    });
+                }
+            }
+            // sorted by name so that the same frame is always reported the same way
+            contacts[start..].sort_by_key(|contact| point_name(&contact.point));
+        }
+
+        FrameContacts { frame, contacts }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A line from (0, 0) to (100, 0), ridden from above.
+    fn floor() -> Surface {
+        Surface {
+            start: Vector2D(0.0, 0.0),
+            end: Vector2D(100.0, 0.0),
+            flipped: false,
+            extended: (false, false),
+        }
+    }
+
+    #[test]
+    fn points_landing_on_the_line_touch_it() {
+        assert!(floor().touches(Vector2D(50.0, 0.0), Vector2D(49.0, -2.0)));
+        assert!(floor().touches(Vector2D(50.0, 1e-9), Vector2D(50.0, 0.0)));
+    }
+
+    #[test]
+    fn points_off_the_line_do_not_touch_it() {
+        assert!(!floor().touches(Vector2D(50.0, -1.0), Vector2D(50.0, -2.0)));
+        assert!(!floor().touches(Vector2D(50.0, 1.0), Vector2D(50.0, 2.0)));
+    }
+
+    #[test]
+    fn points_from_behind_do_not_touch_it() {
+        assert!(!floor().touches(Vector2D(50.0, 0.0), Vector2D(50.0, 2.0)));
+
+        let flipped = Surface {
+            flipped: true,
+            ..floor()
+        };
+        assert!(flipped.touches(Vector2D(50.0, 0.0), Vector2D(50.0, 2.0)));
+        assert!(!flipped.touches(Vector2D(50.0, 0.0), Vector2D(50.0, -2.0)));
+    }
+
+    #[test]
+    fn extensions_reach_past_the_ends() {
+        let extended = Surface {
+            extended: (true, false),
+            ..floor()
+        };
+        let previous = |x: f64| Vector2D(x, -1.0);
+
+        assert!(!floor().touches(Vector2D(-5.0, 0.0), previous(-5.0)));
+        assert!(extended.touches(Vector2D(-5.0, 0.0), previous(-5.0)));
+        assert!(!extended.touches(Vector2D(-11.0, 0.0), previous(-11.0)));
+        assert!(!extended.touches(Vector2D(105.0, 0.0), previous(105.0)));
+    }
+
+    #[test]
+    fn short_lines_extend_by_a_quarter_of_their_length() {
+        let short = Surface {
+            end: Vector2D(20.0, 0.0),
+            extended: (false, true),
+            ..floor()
+        };
+        let previous = |x: f64| Vector2D(x, -1.0);
+
+        assert!(short.touches(Vector2D(24.0, 0.0), previous(24.0)));
+        assert!(!short.touches(Vector2D(26.0, 0.0), previous(26.0)));
+    }
+
+    #[test]
+    fn zero_length_lines_are_never_touched() {
+        let point = Surface {
+            end: Vector2D(0.0, 0.0),
+            ..floor()
+        };
+
+        assert!(!point.touches(Vector2D(0.0, 0.0), Vector2D(0.0, -1.0)));
+    }
+}
@@ -0,0 +1,883 @@
+//! The track that is currently being edited, along with its edit history.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::Sender;
+
+use anyhow::{anyhow, Context, Result};
+use bosh::serialization::boshtf::{
+    BoshTFEntity, BoshTFLine, BoshTFMeta, BoshTFTrack, BoshTFTrigger, RemovedLine, TrackExtras,
+};
+use bosh_rs::rider::Entity;
+use bosh_rs::{Line, Track, Vector2D};
+use serde::Serialize;
+
+use crate::contacts::{ContactFinder, LineContacts};
+use crate::events::{self, RiderEvent};
+use crate::frame_cache::{CompactFrames, FrameCache, FrameCacheStats, MAX_CACHED_FRAMES};
+use crate::history::{Edit, History, HistorySummary};
+use crate::simulation::SimulationMessage;
+use crate::spatial::LineGrid;
+
+pub struct Document {
+    track: Track,
+    extras: TrackExtras,
+    history: History,
+    grid: LineGrid,
+    /// where each line is in `extras.lines`, by line id
+    line_indices: HashMap<u64, usize>,
+    frames: FrameCache,
+    /// background thread that fills in the frame cache, once it has been started
+    simulation: Option<Sender<SimulationMessage>>,
+    /// counts up by one for every edit
+    revision: u64,
+    /// revision at which the whole track was last swapped out
+    reset_revision: u64,
+    /// revision at which each line was last added or changed, by line id
+    added_at: HashMap<u64, u64>,
+    /// revision at which each line was last removed, by line id
+    removed_at: HashMap<u64, u64>,
+    /// revision at which each line was drawn, by line id, for lines that were
+    /// drawn since the track was swapped out
+    created_at: HashMap<u64, u64>,
+    /// line changes made by the edit that is currently happening
+    changes: LineChanges,
+    /// whether a line or entity was put back somewhere other than the end, so
+    /// `track` has to be rebuilt to match the order in `extras`
+    track_out_of_order: bool,
+}
+
+/// Which lines were changed by an edit.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LineChanges {
+    /// revision of the document after the edit
+    pub revision: u64,
+    /// whether the whole track was swapped out, in which case `added` and
+    /// `removed` are empty and every line should be fetched again
+    pub reset: bool,
+    pub added: Vec<u64>,
+    /// ids of lines that no longer exist, or that were replaced by one in `added`
+    pub removed: Vec<u64>,
+}
+
+/// Everything that happened to the lines since some revision, so that the
+/// frontend does not need to fetch every line again.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LineDelta {
+    /// id of the document that the lines belong to, filled in by the command
+    pub document: u64,
+    pub revision: u64,
+    /// whether the whole track was swapped out since, in which case `added`
+    /// contains every line and everything else should be forgotten
+    pub reset: bool,
+    /// ids of lines that no longer exist, or that were replaced by one in `added`
+    pub removed: Vec<u64>,
+    pub added: Vec<BoshTFLine>,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Document {
+            track: Track::new(vec![], vec![]),
+            extras: TrackExtras::default(),
+            history: History::default(),
+            grid: LineGrid::default(),
+            line_indices: HashMap::new(),
+            frames: FrameCache::default(),
+            simulation: None,
+            revision: 0,
+            reset_revision: 0,
+            added_at: HashMap::new(),
+            removed_at: HashMap::new(),
+            created_at: HashMap::new(),
+            changes: LineChanges::default(),
+            track_out_of_order: false,
+        }
+    }
+}
+
+impl Document {
+    pub fn extras(&self) -> &TrackExtras {
+        &self.extras
+    }
+
+    pub fn history(&self) -> HistorySummary {
+        self.history.summary()
+    }
+
+    /// Positions of the entities at `frame`, from the frame cache if possible.
+    pub fn entity_positions_at(&mut self, frame: usize) -> Vec<Entity> {
+        self.frames.get(&self.track, frame)
+    }
+
+    /// Positions of the entities at every `step`th frame from `start` up to, but
+    /// not including, `end`. Ranges longer than [`MAX_CACHED_FRAMES`] are rejected.
+    pub fn entity_positions_range(
+        &mut self,
+        start: usize,
+        end: usize,
+        step: usize,
+    ) -> Result<Vec<Vec<Entity>>> {
+        if step == 0 {
+            return Err(anyhow!("step must be at least 1"));
+        }
+        if end.saturating_sub(start) > MAX_CACHED_FRAMES {
+            return Err(anyhow!(
+                "cannot get more than {} frames at once",
+                MAX_CACHED_FRAMES
+            ));
+        }
+
+        Ok((start..end)
+            .step_by(step)
+            .map(|frame| self.frames.get(&self.track, frame))
+            .collect())
+    }
+
+    /// Same as [`Document::entity_positions_range`], packed into a flat array.
+    pub fn compact_positions_range(
+        &mut self,
+        start: usize,
+        end: usize,
+        step: usize,
+    ) -> Result<CompactFrames> {
+        let entities = self.entity_positions_range(start, end, step)?;
+        let frames = (start..end).step_by(step).collect();
+
+        Ok(CompactFrames::new(frames, &entities))
+    }
+
+    /// Events on frames in `start..end`, like the rider dismounting. The run is
+    /// scanned from frame 0, so that events before `start` are not reported again,
+    /// and so `end` cannot be past [`MAX_CACHED_FRAMES`].
+    pub fn find_events(
+        &mut self,
+        start: usize,
+        end: usize,
+        screen: Option<(Vector2D, Vector2D)>,
+    ) -> Result<Vec<RiderEvent>> {
+        if end > MAX_CACHED_FRAMES {
+            return Err(anyhow!(
+                "cannot look for events past frame {}",
+                MAX_CACHED_FRAMES
+            ));
+        }
+
+        let contacts = ContactFinder::new(&self.grid, &self.extras.lines);
+        let (track, frames) = (&self.track, &mut self.frames);
+        let touching = |location, previous| !contacts.touching(location, previous).is_empty();
+        let mut events = events::find_events(
+            (0..end).map(|frame| frames.get(track, frame)),
+            screen,
+            touching,
+        );
+        events.retain(|event| event.frame >= start);
+
+        Ok(events)
+    }
+
+    /// Lines that each point touches on every frame in `start..end`. Ranges
+    /// longer than [`MAX_CACHED_FRAMES`] are rejected.
+    pub fn line_contacts(&mut self, start: usize, end: usize) -> Result<LineContacts> {
+        if end.saturating_sub(start) > MAX_CACHED_FRAMES {
+            return Err(anyhow!(
+                "cannot get more than {} frames at once",
+                MAX_CACHED_FRAMES
+            ));
+        }
+
+        let contacts = ContactFinder::new(&self.grid, &self.extras.lines);
+
+        let frames: Vec<_> = (start..end)
+            .map(|frame| contacts.frame_contacts(frame, &self.frames.get(&self.track, frame)))
+            .collect();
+        let mut hit_lines: Vec<u64> = frames
+            .iter()
+            .flat_map(|frame| &frame.contacts)
+            .flat_map(|contact| contact.lines.iter().copied())
+            .collect::<HashSet<u64>>()
+            .into_iter()
+            .collect();
+        hit_lines.sort_unstable();
+
+        Ok(LineContacts { frames, hit_lines })
+    }
+
+    pub fn cached_frames(&self) -> usize {
+        self.frames.cached_frames()
+    }
+
+    pub fn last_cached_frame(&self) -> Option<Vec<Entity>> {
+        self.frames.last().cloned()
+    }
+
+    /// Stores frames that were simulated by the background simulation.
+    pub fn store_frames(&mut self, first: usize, frames: Vec<Vec<Entity>>) {
+        self.frames.store(first, frames);
+    }
+
+    pub fn has_simulation(&self) -> bool {
+        self.simulation.is_some()
+    }
+
+    pub fn set_simulation(&mut self, simulation: Sender<SimulationMessage>) {
+        self.simulation = Some(simulation);
+    }
+
+    /// Tells the background simulation about something, if it is running.
+    pub fn send_simulation(&mut self, message: SimulationMessage) {
+        let disconnected = match &self.simulation {
+            Some(simulation) => simulation.send(message).is_err(),
+            None => false,
+        };
+        if disconnected {
+            self.simulation = None;
+        }
+    }
+
+    pub fn frame_cache_stats(&self) -> FrameCacheStats {
+        self.frames.stats()
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Every change made to the lines after `revision`. A revision that this
+    /// document never had, like one from another document, gets every line.
+    pub fn lines_since(&self, revision: u64) -> LineDelta {
+        if revision < self.reset_revision || revision > self.revision {
+            return self.all_lines();
+        }
+
+        LineDelta {
+            document: 0,
+            revision: self.revision,
+            reset: false,
+            // lines that came and went after `revision` were never seen, so they
+            // do not need to be removed either
+            removed: self
+                .removed_at
+                .iter()
+                .filter(|(id, &at)| {
+                    at > revision && self.created_at.get(id).map_or(true, |&c| c <= revision)
+                })
+                .map(|(&id, _)| id)
+                .collect(),
+            added: self
+                .extras
+                .lines
+                .iter()
+                .filter(|l| {
+                    let added_at = l.id.and_then(|id| self.added_at.get(&id));
+                    matches!(added_at, Some(&at) if at > revision)
+                })
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Every line, for starting over from scratch.
+    pub fn all_lines(&self) -> LineDelta {
+        LineDelta {
+            document: 0,
+            revision: self.revision,
+            reset: true,
+            removed: vec![],
+            added: self.extras.lines.clone(),
+        }
+    }
+
+    /// Lines that pass through the rectangle spanned by `min` and `max`, in the
+    /// order they are drawn.
+    pub fn lines_in_rect(&self, min: Vector2D, max: Vector2D) -> Vec<BoshTFLine> {
+        self.lines_with_ids(&self.grid.in_rect(min, max))
+    }
+
+    /// Lines that are at most `radius` away from `point`, in the order they are drawn.
+    pub fn lines_near_point(&self, point: Vector2D, radius: f64) -> Vec<BoshTFLine> {
+        self.lines_with_ids(&self.grid.near_point(point, radius))
+    }
+
+    fn lines_with_ids(&self, ids: &HashSet<u64>) -> Vec<BoshTFLine> {
+        let mut indices: Vec<usize> = ids
+            .iter()
+            .filter_map(|id| self.line_indices.get(id).copied())
+            .collect();
+        indices.sort_unstable();
+
+        indices
+            .into_iter()
+            .map(|index| self.extras.lines[index].clone())
+            .collect()
+    }
+
+    /// Adds a line. Its newly allocated id is the only one in `added`.
+    pub fn add_line(&mut self, line: Line, layer: Option<u64>) -> Result<LineChanges> {
+        self.add_lines(vec![line], layer)
+    }
+
+    /// Removes the line with the given id, if there is one.
+    pub fn remove_line(&mut self, id: u64) -> Result<LineChanges> {
+        self.remove_lines(&[id])
+    }
+
+    /// Adds all of the lines to `layer` as a single edit.
+    pub fn add_lines(&mut self, lines: Vec<Line>, layer: Option<u64>) -> Result<LineChanges> {
+        self.extras.check_line_layer(layer)?;
+
+        self.begin_edit();
+        let mut edits = Vec::with_capacity(lines.len());
+        for line in lines {
+            let added = self.push_line(line, layer)?;
+            edits.push(Edit::AddLine(added));
+        }
+        let label = if edits.len() == 1 {
+            "add line"
+        } else {
+            "add lines"
+        };
+        self.history.record_all(label, edits);
+
+        Ok(self.finish_edit())
+    }
+
+    /// Adds copies of lines, along with their triggers, as a single edit. Every
+    /// copy gets a new id and goes onto `layer`, or onto the layer that it was
+    /// copied from if `layer` is `None` and this track has a layer with that id.
+    pub fn paste_lines(
+        &mut self,
+        copies: Vec<(BoshTFLine, Vec<BoshTFTrigger>)>,
+        layer: Option<u64>,
+    ) -> Result<LineChanges> {
+        let mut pasted = Vec::with_capacity(copies.len());
+        for (mut line, triggers) in copies {
+            line.layer = match layer {
+                Some(layer) => Some(layer),
+                None => line.layer.filter(|&own| self.extras.layer(own).is_ok()),
+            };
+            self.extras.check_line_layer(line.layer)?;
+            pasted.push((line, triggers));
+        }
+
+        self.begin_edit();
+        let mut edits = Vec::with_capacity(pasted.len());
+        for (mut line, mut triggers) in pasted {
+            let id = self.extras.next_line_id;
+            line.id = Some(id);
+            triggers.iter_mut().for_each(|trigger| trigger.set_line(id));
+            let added = RemovedLine {
+                index: self.extras.lines.len(),
+                line,
+                triggers,
+            };
+            self.created_at.insert(id, self.revision);
+            self.restore_line(&added);
+            edits.push(Edit::AddLine(added));
+        }
+        let label = if edits.len() == 1 {
+            "paste line"
+        } else {
+            "paste lines"
+        };
+        self.history.record_all(label, edits);
+
+        Ok(self.finish_edit())
+    }
+
+    /// Removes all lines with the given ids as a single edit. Nothing is removed
+    /// if any of the lines are on a locked layer.
+    pub fn remove_lines(&mut self, ids: &[u64]) -> Result<LineChanges> {
+        let mut indices: Vec<usize> = ids
+            .iter()
+            .filter_map(|id| self.line_indices.get(id).copied())
+            .collect();
+        indices.sort_unstable();
+        indices.dedup();
+        for &index in &indices {
+            self.extras
+                .check_line_layer(self.extras.lines[index].layer)?;
+        }
+
+        // removing from the back keeps the remaining indices valid
+        self.begin_edit();
+        let mut edits = Vec::with_capacity(indices.len());
+        for index in indices.into_iter().rev() {
+            edits.push(Edit::RemoveLine(self.delete_line(index)));
+        }
+        let label = if edits.len() == 1 {
+            "remove line"
+        } else {
+            "remove lines"
+        };
+        self.history.record_all(label, edits);
+
+        Ok(self.finish_edit())
+    }
+
+    /// Swaps out existing lines for new versions with the same ids as a single
+    /// edit. Nothing is replaced if any of the lines are missing or locked.
+    pub fn replace_lines(&mut self, lines: Vec<BoshTFLine>) -> Result<LineChanges> {
+        let mut replacements = Vec::with_capacity(lines.len());
+        for line in lines {
+            let id = line.id.context("replacement lines must have an id")?;
+            let index = *self
+                .line_indices
+                .get(&id)
+                .with_context(|| format!("no line with id {id}"))?;
+            self.extras
+                .check_line_layer(self.extras.lines[index].layer)?;
+            self.extras.check_line_layer(line.layer)?;
+            replacements.push((index, line));
+        }
+
+        self.begin_edit();
+        let mut edits = Vec::with_capacity(replacements.len() * 2);
+        for (index, line) in replacements {
+            let removed = self.delete_line(index);
+            let added = RemovedLine {
+                index,
+                line,
+                triggers: removed.triggers.clone(),
+            };
+            self.restore_line(&added);
+
+            edits.push(Edit::RemoveLine(removed));
+            edits.push(Edit::AddLine(added));
+        }
+        self.history.record_all("edit lines", edits);
+
+        Ok(self.finish_edit())
+    }
+
+    pub fn add_entity(&mut self, entity: BoshTFEntity) {
+        self.begin_edit();
+        self.track.create_entity((&entity).into());
+        self.frames.invalidate_all();
+        self.extras.entities.push(entity.clone());
+
+        let index = self.extras.entities.len() - 1;
+        self.history
+            .record("add rider", Edit::AddEntity { index, entity });
+    }
+
+    pub fn remove_entity(&mut self, entity: &BoshTFEntity) {
+        if let Some((index, entity)) = self.extras.remove_entity(entity) {
+            self.begin_edit();
+            self.track.remove_entity((&entity).into());
+            self.frames.invalidate_all();
+            self.history
+                .record("remove rider", Edit::RemoveEntity { index, entity });
+        }
+    }
+
+    /// Swaps out the whole track, ie when clearing or loading a track.
+    pub fn replace_track(&mut self, label: &str, track: &BoshTFTrack) -> LineChanges {
+        self.begin_edit();
+        let before = BoshTFTrack::from(&self.extras);
+        self.set_track(track);
+
+        let after = BoshTFTrack::from(&self.extras);
+        self.history.record(
+            label,
+            Edit::ReplaceTrack {
+                before: Box::new(before),
+                after: Box::new(after),
+            },
+        );
+
+        self.finish_edit()
+    }
+
+    pub fn set_meta(&mut self, meta: BoshTFMeta) {
+        self.begin_edit();
+        let before = std::mem::replace(&mut self.extras.meta, meta.clone());
+        self.history.record(
+            "edit track info",
+            Edit::SetMeta {
+                before: Box::new(before),
+                after: Box::new(meta),
+            },
+        );
+    }
+
+    /// Makes a change to the layers, which is undone by restoring all layers
+    /// to how they were before.
+    pub fn edit_layers<T>(
+        &mut self,
+        label: &str,
+        edit: impl FnOnce(&mut TrackExtras) -> Result<T>,
+    ) -> Result<T> {
+        let before = self.extras.layers.clone();
+        let result = edit(&mut self.extras);
+        if result.is_err() {
+            self.extras.layers = before;
+            return result;
+        }
+
+        self.begin_edit();
+        let after = self.extras.layers.clone();
+        self.history
+            .record(label, Edit::SetLayers { before, after });
+
+        result
+    }
+
+    /// Starts grouping edits so that they are undone as one.
+    pub fn begin_group(&mut self, label: String) -> Result<()> {
+        self.history.begin_group(label)
+    }
+
+    pub fn end_group(&mut self) -> Result<()> {
+        self.history.end_group()
+    }
+
+    /// Undoes the most recent transaction, returning the lines it changed.
+    pub fn undo(&mut self) -> Result<LineChanges> {
+        let transaction = self.history.pop_undo().context("nothing to undo")?;
+        self.begin_edit();
+        for edit in transaction.edits.iter().rev() {
+            self.revert(edit);
+        }
+        self.history.push_redo(transaction);
+
+        Ok(self.finish_edit())
+    }
+
+    /// Redoes the most recently undone transaction, returning the lines it changed.
+    pub fn redo(&mut self) -> Result<LineChanges> {
+        let transaction = self.history.pop_redo().context("nothing to redo")?;
+        self.begin_edit();
+        for edit in &transaction.edits {
+            self.apply(edit);
+        }
+        self.history.push_undo(transaction);
+
+        Ok(self.finish_edit())
+    }
+
+    fn apply(&mut self, edit: &Edit) {
+        match edit {
+            Edit::AddLine(added) => self.restore_line(added),
+            Edit::RemoveLine(removed) => self.take_line(removed),
+            Edit::AddEntity { index, entity } => self.insert_entity(*index, entity),
+            Edit::RemoveEntity { entity, .. } => self.take_entity(entity),
+            Edit::ReplaceTrack { after, .. } => self.set_track(after),
+            Edit::SetMeta { after, .. } => self.extras.meta = (**after).clone(),
+            Edit::SetLayers { after, .. } => self.extras.layers = after.clone(),
+        }
+    }
+
+    fn revert(&mut self, edit: &Edit) {
+        match edit {
+            Edit::AddLine(added) => self.take_line(added),
+            Edit::RemoveLine(removed) => self.restore_line(removed),
+            Edit::AddEntity { entity, .. } => self.take_entity(entity),
+            Edit::RemoveEntity { index, entity } => self.insert_entity(*index, entity),
+            Edit::ReplaceTrack { before, .. } => self.set_track(before),
+            Edit::SetMeta { before, .. } => self.extras.meta = (**before).clone(),
+            Edit::SetLayers { before, .. } => self.extras.layers = before.clone(),
+        }
+    }
+
+    /// Starts keeping track of the lines changed by a new revision.
+    fn begin_edit(&mut self) {
+        self.revision += 1;
+        self.send_simulation(SimulationMessage::TrackChanged);
+        self.changes = LineChanges {
+            revision: self.revision,
+            ..LineChanges::default()
+        };
+    }
+
+    fn finish_edit(&mut self) -> LineChanges {
+        if self.track_out_of_order {
+            // collisions depend on the order of lines, so it has to match the saved order
+            self.track = Track::new(
+                self.extras.entities.iter().map(Entity::from).collect(),
+                self.extras.lines.iter().map(|l| l.line.clone()).collect(),
+            );
+            self.track_out_of_order = false;
+        }
+
+        std::mem::take(&mut self.changes)
+    }
+
+    fn line_added(&mut self, id: Option<u64>, line: &Line) {
+        self.frames.invalidate_line(line);
+        if let Some(id) = id {
+            self.grid.insert(id, line);
+            self.added_at.insert(id, self.revision);
+            self.changes.added.push(id);
+        }
+    }
+
+    fn line_removed(&mut self, id: Option<u64>, line: &Line) {
+        self.frames.invalidate_line(line);
+        if let Some(id) = id {
+            self.grid.remove(id);
+            self.added_at.remove(&id);
+            self.removed_at.insert(id, self.revision);
+            if let Some(index) = self.changes.added.iter().position(|&a| a == id) {
+                self.changes.added.remove(index);
+            }
+            self.changes.removed.push(id);
+        }
+    }
+
+    fn set_track(&mut self, track: &BoshTFTrack) {
+        self.track = track.into();
+        self.extras = track.into();
+
+        self.reset_revision = self.revision;
+        self.added_at.clear();
+        self.removed_at.clear();
+        self.created_at.clear();
+        self.frames.invalidate_all();
+        self.grid.clear();
+        for line in &self.extras.lines {
+            if let Some(id) = line.id {
+                self.grid.insert(id, &line.line);
+            }
+        }
+        self.line_indices.clear();
+        self.reindex_lines(0);
+        self.changes.reset = true;
+        self.changes.added.clear();
+        self.changes.removed.clear();
+    }
+
+    /// Adds a brand new line to the end of the track.
+    fn push_line(&mut self, line: Line, layer: Option<u64>) -> Result<RemovedLine> {
+        let id = self.extras.add_line(line.clone(), layer)?;
+        self.created_at.insert(id, self.revision);
+        self.line_added(Some(id), &line);
+        self.track.add_line(line);
+
+        let index = self.extras.lines.len() - 1;
+        self.reindex_lines(index);
+        Ok(RemovedLine {
+            index,
+            line: self.extras.lines[index].clone(),
+            triggers: vec![],
+        })
+    }
+
+    fn delete_line(&mut self, index: usize) -> RemovedLine {
+        let removed = self.extras.take_line(index);
+        if let Some(id) = removed.line.id {
+            self.line_indices.remove(&id);
+        }
+        self.reindex_lines(index);
+        self.track.remove_line(&removed.line.line);
+        self.line_removed(removed.line.id, &removed.line.line);
+
+        removed
+    }
+
+    fn restore_line(&mut self, removed: &RemovedLine) {
+        self.track.add_line(removed.line.line.clone());
+        self.extras.restore_line(removed.clone());
+        let index = removed.index.min(self.extras.lines.len() - 1);
+        self.track_out_of_order |= index + 1 < self.extras.lines.len();
+        self.reindex_lines(index);
+        if let Some(id) = removed.line.id {
+            self.extras.next_line_id = self.extras.next_line_id.max(id + 1);
+        }
+        self.line_added(removed.line.id, &removed.line.line);
+    }
+
+    fn take_line(&mut self, removed: &RemovedLine) {
+        let index = removed
+            .line
+            .id
+            .and_then(|id| self.line_indices.get(&id).copied());
+        if let Some(index) = index {
+            self.delete_line(index);
+        }
+    }
+
+    /// Updates `line_indices` for the lines from `start` onwards, after lines
+    /// were inserted or removed there.
+    fn reindex_lines(&mut self, start: usize) {
+        for (index, line) in self.extras.lines.iter().enumerate().skip(start) {
+            if let Some(id) = line.id {
+                self.line_indices.insert(id, index);
+            }
+        }
+    }
+
+    fn insert_entity(&mut self, index: usize, entity: &BoshTFEntity) {
+        let index = index.min(self.extras.entities.len());
+        self.track.create_entity(entity.into());
+        self.frames.invalidate_all();
+        self.track_out_of_order |= index < self.extras.entities.len();
+        self.extras.entities.insert(index, entity.clone());
+    }
+
+    fn take_entity(&mut self, entity: &BoshTFEntity) {
+        if let Some((_, entity)) = self.extras.remove_entity(entity) {
+            self.track.remove_entity((&entity).into());
+            self.frames.invalidate_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bosh::serialization::boshtf::BoshTFColor;
+
+    use super::*;
+
+    fn line(x: f64) -> Line {
+        Line::builder().point(x, 0.0).point(x + 10.0, 0.0).build()
+    }
+
+    fn ids(lines: &[BoshTFLine]) -> Vec<u64> {
+        lines.iter().filter_map(|l| l.id).collect()
+    }
+
+    #[test]
+    fn added_lines_are_in_the_delta() {
+        let mut document = Document::default();
+        let changes = document.add_line(line(0.0), None).unwrap();
+        assert_eq!(changes.revision, 1);
+        assert_eq!(changes.added.len(), 1);
+
+        let delta = document.lines_since(0);
+        assert!(!delta.reset);
+        assert_eq!(delta.revision, 1);
+        assert_eq!(ids(&delta.added), changes.added);
+        assert!(delta.removed.is_empty());
+
+        let delta = document.lines_since(1);
+        assert!(delta.added.is_empty() && delta.removed.is_empty());
+    }
+
+    #[test]
+    fn lines_added_and_removed_since_are_left_out() {
+        let mut document = Document::default();
+        let id = document.add_line(line(0.0), None).unwrap().added[0];
+        document.remove_line(id).unwrap();
+
+        let delta = document.lines_since(0);
+        assert!(!delta.reset);
+        assert!(delta.added.is_empty());
+        assert!(delta.removed.is_empty());
+
+        assert_eq!(document.lines_since(1).removed, [id]);
+    }
+
+    #[test]
+    fn unknown_revisions_get_every_line() {
+        let mut document = Document::default();
+        document
+            .add_lines(vec![line(0.0), line(20.0)], None)
+            .unwrap();
+
+        let delta = document.lines_since(99);
+        assert!(delta.reset);
+        assert_eq!(delta.added.len(), 2);
+
+        let changes = document.replace_track("clear track", &BoshTFTrack::default());
+        assert!(changes.reset);
+        let delta = document.lines_since(1);
+        assert!(delta.reset);
+        assert!(delta.added.is_empty());
+    }
+
+    #[test]
+    fn undo_and_redo_report_their_lines() {
+        let mut document = Document::default();
+        let id = document.add_line(line(0.0), None).unwrap().added[0];
+        document.remove_line(id).unwrap();
+
+        let changes = document.undo().unwrap();
+        assert_eq!(changes.revision, 3);
+        assert_eq!(changes.added, [id]);
+        assert!(changes.removed.is_empty());
+        let delta = document.lines_since(2);
+        assert_eq!(ids(&delta.added), [id]);
+        assert!(delta.removed.is_empty());
+
+        let changes = document.redo().unwrap();
+        assert_eq!(changes.revision, 4);
+        assert_eq!(changes.removed, [id]);
+        assert!(changes.added.is_empty());
+        let delta = document.lines_since(3);
+        assert!(delta.added.is_empty());
+        assert_eq!(delta.removed, [id]);
+    }
+
+    #[test]
+    fn pasted_lines_keep_their_looks_and_triggers() {
+        let copy = BoshTFLine {
+            id: Some(7),
+            width: Some(2.0),
+            color: Some(BoshTFColor { r: 1, g: 2, b: 3 }),
+            layer: Some(99),
+            line: line(0.0),
+        };
+        let trigger = BoshTFTrigger::Zoom {
+            line: 7,
+            target: 2.0,
+            frames: 40,
+        };
+        let mut document = Document::default();
+        let changes = document
+            .paste_lines(vec![(copy, vec![trigger])], None)
+            .unwrap();
+
+        let pasted = &document.extras().lines[0];
+        assert_eq!(pasted.id, Some(changes.added[0]));
+        assert_eq!(pasted.width, Some(2.0));
+        assert_eq!(pasted.color, Some(BoshTFColor { r: 1, g: 2, b: 3 }));
+        // there is no layer 99 in this track
+        assert_eq!(pasted.layer, None);
+        assert_eq!(document.extras().triggers.len(), 1);
+        assert_eq!(document.extras().triggers[0].line(), changes.added[0]);
+
+        document.undo().unwrap();
+        assert!(document.extras().lines.is_empty());
+        assert!(document.extras().triggers.is_empty());
+    }
+
+    #[test]
+    fn undo_puts_lines_back_in_order() {
+        let mut document = Document::default();
+        let added = document
+            .add_lines(vec![line(0.0), line(20.0), line(40.0)], None)
+            .unwrap()
+            .added;
+        document.remove_line(added[1]).unwrap();
+        document.undo().unwrap();
+
+        assert_eq!(ids(&document.extras().lines), added);
+        let found = document.lines_in_rect(Vector2D(-10.0, -10.0), Vector2D(60.0, 10.0));
+        assert_eq!(ids(&found), added);
+    }
+
+    #[test]
+    fn riders_keep_whether_they_are_remountable() {
+        let sled = |remountable: bool| BoshTFEntity::BoshSled {
+            velocity: Vector2D(0.4, 0.0),
+            position: Vector2D(0.0, 0.0),
+            remountable,
+            frictionless: false,
+        };
+        let mut document = Document::default();
+        document.add_entity(sled(true));
+        let rider = document.entity_positions_at(0).remove(0);
+        assert_eq!(document.extras().entity_from(&rider), sled(true));
+
+        document.undo().unwrap();
+        document.add_entity(sled(false));
+        document.add_entity(sled(true));
+        // both riders start out the same, so only the flag tells them apart
+        document.remove_entity(&sled(true));
+        assert_eq!(document.extras().entities, vec![sled(false)]);
+        document.undo().unwrap();
+        assert_eq!(document.extras().entities, vec![sled(false), sled(true)]);
+    }
+}
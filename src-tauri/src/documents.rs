@@ -0,0 +1,271 @@
+//! Every open document, and which one each window is showing.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+use crate::document::Document;
+
+pub type DocumentId = u64;
+
+/// Managed as Tauri state, so that every window can have its own tracks open.
+#[derive(Default)]
+pub struct Documents {
+    registry: Mutex<Registry>,
+}
+
+#[derive(Default)]
+struct Registry {
+    next_id: DocumentId,
+    documents: HashMap<DocumentId, OpenDocument>,
+    /// document that each window is showing, by window label
+    active: HashMap<String, DocumentId>,
+}
+
+struct OpenDocument {
+    /// label of the window that opened the document, or took it over when that
+    /// window closed
+    window: String,
+    document: Arc<Mutex<Document>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DocumentInfo {
+    pub id: DocumentId,
+    pub window: String,
+    /// whether this is the document that the window is showing
+    pub active: bool,
+}
+
+impl Documents {
+    /// The registry only maps ids to documents, so it is always safe to keep
+    /// using after a panic.
+    fn registry(&self) -> MutexGuard<Registry> {
+        self.registry.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The document that `window` is showing, opening a new one if it has none.
+    pub fn active(&self, window: &str) -> (DocumentId, Arc<Mutex<Document>>) {
+        let mut registry = self.registry();
+        let id = match registry.active.get(window) {
+            Some(&id) => id,
+            None => registry.open(window),
+        };
+
+        (id, registry.documents[&id].document.clone())
+    }
+
+    pub fn get(&self, id: DocumentId) -> Result<Arc<Mutex<Document>>> {
+        self.registry()
+            .documents
+            .get(&id)
+            .map(|open| open.document.clone())
+            .ok_or_else(|| anyhow!("no document with id {id}"))
+    }
+
+    /// Opens an empty document and shows it in `window`.
+    pub fn open(&self, window: &str) -> DocumentId {
+        self.registry().open(window)
+    }
+
+    /// Shows a document in `window`, which may have been opened by another window.
+    pub fn select(&self, window: &str, id: DocumentId) -> Result<()> {
+        let mut registry = self.registry();
+        if !registry.documents.contains_key(&id) {
+            return Err(anyhow!("no document with id {id}"));
+        }
+        registry.active.insert(window.to_string(), id);
+
+        Ok(())
+    }
+
+    /// Closes a document. Windows that were showing it get a new empty document
+    /// the next time they use one.
+    pub fn close(&self, id: DocumentId) -> Result<()> {
+        let mut registry = self.registry();
+        registry
+            .documents
+            .remove(&id)
+            .ok_or_else(|| anyhow!("no document with id {id}"))?;
+        registry.active.retain(|_, active| *active != id);
+
+        Ok(())
+    }
+
+    /// Closes every document that `window` opened, except for documents that
+    /// other windows are showing, which are handed over to one of them.
+    pub fn close_window(&self, window: &str) {
+        let mut registry = self.registry();
+        registry.active.remove(window);
+
+        let Registry {
+            documents, active, ..
+        } = &mut *registry;
+        documents.retain(|&id, open| {
+            if open.window != window {
+                return true;
+            }
+            let shown_by = active
+                .iter()
+                .filter(|(_, &shown)| shown == id)
+                .map(|(label, _)| label)
+                .min();
+            match shown_by {
+                Some(label) => {
+                    open.window = label.clone();
+                    true
+                }
+                None => false,
+            }
+        });
+    }
+
+    /// Every open document, from oldest to newest.
+    pub fn list(&self, window: &str) -> Vec<DocumentInfo> {
+        let registry = self.registry();
+        let active = registry.active.get(window).copied();
+
+        let mut documents: Vec<DocumentInfo> = registry
+            .documents
+            .iter()
+            .map(|(&id, open)| DocumentInfo {
+                id,
+                window: open.window.clone(),
+                active: Some(id) == active,
+            })
+            .collect();
+        documents.sort_by_key(|info| info.id);
+
+        documents
+    }
+}
+
+/// Locks a document, even if a command panicked while holding the lock. One
+/// failed command should not make the document unusable for every later one.
+pub fn lock_document(document: &Mutex<Document>) -> MutexGuard<Document> {
+    document.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Registry {
+    fn open(&mut self, window: &str) -> DocumentId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.documents.insert(
+            id,
+            OpenDocument {
+                window: window.to_string(),
+                document: Arc::new(Mutex::new(Document::default())),
+            },
+        );
+        self.active.insert(window.to_string(), id);
+
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+
+    fn listed(documents: &Documents, window: &str) -> Vec<(DocumentId, String, bool)> {
+        documents
+            .list(window)
+            .into_iter()
+            .map(|info| (info.id, info.window, info.active))
+            .collect()
+    }
+
+    #[test]
+    fn windows_get_a_document_when_they_need_one() {
+        let documents = Documents::default();
+        let (first, _) = documents.active("main");
+        assert_eq!(documents.active("main").0, first);
+
+        let second = documents.open("main");
+        assert_ne!(second, first);
+        assert_eq!(documents.active("main").0, second);
+        assert_eq!(
+            listed(&documents, "main"),
+            [
+                (first, "main".to_string(), false),
+                (second, "main".to_string(), true)
+            ]
+        );
+    }
+
+    #[test]
+    fn windows_can_show_each_others_documents() {
+        let documents = Documents::default();
+        let id = documents.open("main");
+        documents.open("other");
+
+        documents.select("other", id).unwrap();
+        assert_eq!(documents.active("other").0, id);
+        assert!(documents.select("other", 99).is_err());
+        assert_eq!(documents.active("other").0, id);
+    }
+
+    #[test]
+    fn closing_a_document_clears_it_from_windows() {
+        let documents = Documents::default();
+        let id = documents.open("main");
+        documents.select("other", id).unwrap();
+
+        documents.close(id).unwrap();
+        assert!(documents.get(id).is_err());
+        assert!(documents.close(id).is_err());
+        assert_ne!(documents.active("main").0, id);
+        assert_ne!(documents.active("other").0, id);
+    }
+
+    #[test]
+    fn closing_a_window_keeps_documents_that_others_show() {
+        let documents = Documents::default();
+        let shared = documents.open("main");
+        let private = documents.open("main");
+        documents.select("other", shared).unwrap();
+
+        documents.close_window("main");
+        assert!(documents.get(private).is_err());
+        assert!(documents.get(shared).is_ok());
+        assert_eq!(documents.active("other").0, shared);
+        assert_eq!(
+            listed(&documents, "other"),
+            [(shared, "other".to_string(), true)]
+        );
+
+        documents.close_window("other");
+        assert!(documents.get(shared).is_err());
+    }
+
+    #[test]
+    fn poisoned_locks_are_recovered() {
+        let documents = Arc::new(Documents::default());
+        let (id, document) = documents.active("main");
+
+        let poisoner = document.clone();
+        thread::spawn(move || {
+            let _document = poisoner.lock().unwrap();
+            panic!("poisoning the document");
+        })
+        .join()
+        .unwrap_err();
+        assert!(document.is_poisoned());
+        assert_eq!(lock_document(&document).revision(), 0);
+
+        let poisoner = documents.clone();
+        thread::spawn(move || {
+            let _registry = poisoner.registry.lock().unwrap();
+            panic!("poisoning the registry");
+        })
+        .join()
+        .unwrap_err();
+        assert!(documents.registry.is_poisoned());
+        assert_eq!(documents.active("main").0, id);
+    }
+}
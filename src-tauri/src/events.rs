@@ -0,0 +1,362 @@
+//! Moments in a run that the timeline marks, like the rider falling off or the
+//! sled breaking.
+//!
+//! bosh-rs does not report these itself, so they are worked out from how the
+//! entities change from one frame to the next.
+
+use bosh::frame_dump::point_name;
+use bosh_rs::rider::{BoneType, Entity, Joint, PointIndex};
+use bosh_rs::Vector2D;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RiderEventKind {
+    /// a mount bone broke, the rider's body folded the wrong way or the sled broke
+    Dismounted,
+    /// the sled folded the wrong way
+    SledBroken,
+    /// a point touched a physics line for the first time
+    FirstContact,
+    /// every point went outside of the screen
+    LeftScreen,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RiderEvent {
+    pub frame: usize,
+    /// index of the entity in the track
+    pub entity: usize,
+    pub kind: RiderEventKind,
+}
+
+/// What an entity looks like on a single frame.
+#[derive(Debug, Clone, Copy, Default)]
+struct Observation {
+    /// whether the entity has any mount bones left
+    has_mount: bool,
+    /// whether the rider's body folded the wrong way
+    body_folded: bool,
+    sled_folded: bool,
+    touching: bool,
+    on_screen: bool,
+}
+
+/// What has happened to an entity up to some frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct EntityState {
+    /// Falling off is sticky: the rider stays dismounted, even if the body
+    /// unfolds, until the mount bones are taken away and given back again.
+    mounted: bool,
+    had_mount: bool,
+    /// a broken sled stays broken, even if it folds back
+    sled_broken: bool,
+    touched: bool,
+    on_screen: bool,
+}
+
+impl EntityState {
+    /// The state on the first frame, which never has any events other than
+    /// first contact.
+    fn first(observation: Observation) -> (EntityState, Vec<RiderEventKind>) {
+        let state = EntityState {
+            mounted: observation.has_mount && !observation.body_folded && !observation.sled_folded,
+            had_mount: observation.has_mount,
+            sled_broken: observation.sled_folded,
+            touched: observation.touching,
+            on_screen: observation.on_screen,
+        };
+        let events = if observation.touching {
+            vec![RiderEventKind::FirstContact]
+        } else {
+            vec![]
+        };
+
+        (state, events)
+    }
+
+    /// The state after another frame, and the events that happened on it. A
+    /// sled breaking under a mounted rider dismounts them on the same frame.
+    fn next(self, observation: Observation) -> (EntityState, Vec<RiderEventKind>) {
+        let sled_broken = self.sled_broken || observation.sled_folded;
+        let upright = observation.has_mount && !observation.body_folded && !sled_broken;
+        let remounted = !self.had_mount && observation.has_mount;
+        let state = EntityState {
+            mounted: upright && (self.mounted || remounted),
+            had_mount: observation.has_mount,
+            sled_broken,
+            touched: self.touched || observation.touching,
+            on_screen: observation.on_screen,
+        };
+
+        let mut events = vec![];
+        if !self.sled_broken && state.sled_broken {
+            events.push(RiderEventKind::SledBroken);
+        }
+        if self.mounted && !state.mounted {
+            events.push(RiderEventKind::Dismounted);
+        }
+        if !self.touched && state.touched {
+            events.push(RiderEventKind::FirstContact);
+        }
+        if self.on_screen && !state.on_screen {
+            events.push(RiderEventKind::LeftScreen);
+        }
+
+        (state, events)
+    }
+}
+
+/// Finds events in `frames`, which have to start at frame 0 and include every
+/// frame after that, so that each frame can be compared with the one before it.
+///
+/// `touching` tells whether a point, given its location and previous location,
+/// is touching a physics line, and `screen` is
+/// the visible area as `(min, max)`, without which nothing leaves the screen.
+pub fn find_events(
+    frames: impl IntoIterator<Item = Vec<Entity>>,
+    screen: Option<(Vector2D, Vector2D)>,
+    mut touching: impl FnMut(Vector2D, Vector2D) -> bool,
+) -> Vec<RiderEvent> {
+    let mut events = vec![];
+    let mut states: Vec<Option<EntityState>> = vec![];
+
+    for (frame, entities) in frames.into_iter().enumerate() {
+        if states.len() < entities.len() {
+            states.resize_with(entities.len(), || None);
+        }
+
+        for (index, entity) in entities.iter().enumerate() {
+            let observation = observe(entity, screen, &mut touching);
+            let (state, kinds) = match states[index] {
+                Some(previous) => previous.next(observation),
+                None => EntityState::first(observation),
+            };
+            states[index] = Some(state);
+
+            events.extend(kinds.into_iter().map(|kind| RiderEvent {
+                frame,
+                entity: index,
+                kind,
+            }));
+        }
+    }
+
+    events
+}
+
+fn observe(
+    entity: &Entity,
+    screen: Option<(Vector2D, Vector2D)>,
+    mut touching: impl FnMut(Vector2D, Vector2D) -> bool,
+) -> Observation {
+    let (sled_joints, body_joints): (Vec<&Joint>, Vec<&Joint>) =
+        entity.joints.iter().partition(|joint| is_sled_joint(joint));
+
+    Observation {
+        has_mount: entity
+            .bones
+            .iter()
+            .any(|bone| matches!(bone.bone_type, BoneType::Mount { .. })),
+        body_folded: body_joints.iter().any(|joint| is_crossed(entity, joint)),
+        sled_folded: sled_joints.iter().any(|joint| is_crossed(entity, joint)),
+        touching: entity
+            .points
+            .values()
+            .any(|point| touching(point.location, point.previous_location)),
+        on_screen: screen.map_or(true, |(min, max)| is_on_screen(entity, min, max)),
+    }
+}
+
+/// Joints between sled points keep the sled in shape, every other joint keeps
+/// the rider on it.
+fn is_sled_joint(joint: &Joint) -> bool {
+    [
+        &joint.pair1.0,
+        &joint.pair1.1,
+        &joint.pair2.0,
+        &joint.pair2.1,
+    ]
+    .iter()
+    .all(|&index| point_name(index).starts_with("Sled"))
+}
+
+/// Whether the two bones of a joint have folded past each other.
+fn is_crossed(entity: &Entity, joint: &Joint) -> bool {
+    let location = |index: &PointIndex| entity.points.get(index).map(|point| point.location);
+    let (a0, a1, b0, b1) = match (
+        location(&joint.pair1.0),
+        location(&joint.pair1.1),
+        location(&joint.pair2.0),
+        location(&joint.pair2.1),
+    ) {
+        (Some(a0), Some(a1), Some(b0), Some(b1)) => (a0, a1, b0, b1),
+        _ => return false,
+    };
+    let a = (a1.0 - a0.0, a1.1 - a0.1);
+    let b = (b1.0 - b0.0, b1.1 - b0.1);
+
+    a.0 * b.1 - a.1 * b.0 < 0.0
+}
+
+fn is_on_screen(entity: &Entity, min: Vector2D, max: Vector2D) -> bool {
+    entity.points.values().any(|point| {
+        let Vector2D(x, y) = point.location;
+        x >= min.0 && x <= max.0 && y >= min.1 && y <= max.1
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn riding() -> Observation {
+        Observation {
+            has_mount: true,
+            on_screen: true,
+            ..Observation::default()
+        }
+    }
+
+    /// The events of each frame after the first.
+    fn run(observations: &[Observation]) -> Vec<Vec<RiderEventKind>> {
+        let (mut state, _) = EntityState::first(observations[0]);
+        observations[1..]
+            .iter()
+            .map(|&observation| {
+                let (next, events) = state.next(observation);
+                state = next;
+                events
+            })
+            .collect()
+    }
+
+    #[test]
+    fn default_rider_starts_mounted() {
+        let observation = observe(&Entity::default_boshsled(), None, |_, _| false);
+        let (state, events) = EntityState::first(observation);
+
+        assert!(state.mounted);
+        assert!(!state.sled_broken);
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn dismounting_is_reported_once() {
+        let folded = Observation {
+            body_folded: true,
+            ..riding()
+        };
+        let events = run(&[riding(), folded, riding(), folded, riding()]);
+
+        assert_eq!(
+            events,
+            [vec![RiderEventKind::Dismounted], vec![], vec![], vec![]]
+        );
+    }
+
+    #[test]
+    fn losing_mount_bones_dismounts() {
+        let unmounted = Observation {
+            has_mount: false,
+            ..riding()
+        };
+        let events = run(&[riding(), unmounted, unmounted]);
+
+        assert_eq!(events, [vec![RiderEventKind::Dismounted], vec![]]);
+    }
+
+    #[test]
+    fn remounting_is_not_reported() {
+        let unmounted = Observation {
+            has_mount: false,
+            ..riding()
+        };
+        let folded = Observation {
+            body_folded: true,
+            ..riding()
+        };
+        let events = run(&[riding(), unmounted, riding(), folded]);
+
+        assert_eq!(
+            events,
+            [
+                vec![RiderEventKind::Dismounted],
+                vec![],
+                vec![RiderEventKind::Dismounted]
+            ]
+        );
+    }
+
+    #[test]
+    fn broken_sled_dismounts_rider() {
+        let broken = Observation {
+            sled_folded: true,
+            ..riding()
+        };
+        let events = run(&[riding(), broken, riding()]);
+
+        assert_eq!(
+            events,
+            [
+                vec![RiderEventKind::SledBroken, RiderEventKind::Dismounted],
+                vec![]
+            ]
+        );
+    }
+
+    #[test]
+    fn sled_breaking_after_dismount_is_still_reported() {
+        let folded = Observation {
+            body_folded: true,
+            ..riding()
+        };
+        let broken = Observation {
+            sled_folded: true,
+            ..folded
+        };
+        let events = run(&[riding(), folded, broken]);
+
+        assert_eq!(
+            events,
+            [
+                vec![RiderEventKind::Dismounted],
+                vec![RiderEventKind::SledBroken]
+            ]
+        );
+    }
+
+    #[test]
+    fn first_contact_and_leaving_screen_are_reported_once() {
+        let touching = Observation {
+            touching: true,
+            ..riding()
+        };
+        let gone = Observation {
+            on_screen: false,
+            ..riding()
+        };
+        let events = run(&[riding(), touching, touching, gone, gone]);
+
+        assert_eq!(
+            events,
+            [
+                vec![RiderEventKind::FirstContact],
+                vec![],
+                vec![RiderEventKind::LeftScreen],
+                vec![]
+            ]
+        );
+    }
+
+    #[test]
+    fn touching_on_the_first_frame_is_first_contact() {
+        let touching = Observation {
+            touching: true,
+            ..riding()
+        };
+        let (_, events) = EntityState::first(touching);
+
+        assert_eq!(events, [RiderEventKind::FirstContact]);
+    }
+}
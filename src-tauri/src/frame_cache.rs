@@ -0,0 +1,272 @@
+//! Cache of simulated frames, so that scrubbing through the timeline does not
+//! need to ask the physics engine for frames it has already given us.
+//!
+//! The cache keeps every frame before the earliest one that an edit could have
+//! changed. Missing frames are simulated one step at a time from the last cached
+//! frame, since asking the engine for a frame simulates it from the start.
+
+use bosh_rs::physics::advance_frame::frame_after;
+use bosh_rs::rider::{Entity, PointIndex};
+use bosh_rs::{Line, LineType, Track, Vector2D};
+use serde::Serialize;
+
+/// How close a point has to be to a line to collide with it.
+pub(crate) const HITBOX_MARGIN: f64 = 10.0;
+
+/// Longest that an extended line can be extended by, relative to its length.
+pub(crate) const MAX_EXTENSION: f64 = 0.25;
+
+/// Frames past this are handed out without being cached, to keep memory bounded.
+/// This is ten minutes at 40 frames per second.
+pub const MAX_CACHED_FRAMES: usize = 40 * 60 * 10;
+
+#[derive(Debug, Clone, Default)]
+pub struct FrameCache {
+    /// positions of the entities at each frame, starting from frame 0
+    frames: Vec<Vec<Entity>>,
+    hits: u64,
+    misses: u64,
+    invalidations: u64,
+    last_invalidated_from: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameCacheStats {
+    pub cached_frames: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// how many edits caused frames to be forgotten
+    pub invalidations: u64,
+    /// the first frame that was forgotten by the most recent invalidation
+    pub last_invalidated_from: Option<usize>,
+}
+
+/// Point locations for many frames packed into one flat array, which is much
+/// smaller and quicker to send to the frontend than full entities.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompactFrames {
+    /// which frames are included, in order
+    pub frames: Vec<usize>,
+    /// names of each entity's points, in the order their locations are packed
+    pub points: Vec<Vec<PointIndex>>,
+    /// `x, y` of every point of every entity, for each frame one after another
+    pub locations: Vec<f64>,
+}
+
+impl CompactFrames {
+    pub fn new(frames: Vec<usize>, entities: &[Vec<Entity>]) -> CompactFrames {
+        let points: Vec<Vec<PointIndex>> = entities
+            .first()
+            .map(|first| {
+                first
+                    .iter()
+                    .map(|entity| entity.points.keys().cloned().collect())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut locations = vec![];
+        for frame in entities {
+            for (entity, names) in frame.iter().zip(&points) {
+                for name in names {
+                    let location = entity
+                        .points
+                        .get(name)
+                        .map_or(Vector2D(f64::NAN, f64::NAN), |p| p.location);
+                    locations.push(location.0);
+                    locations.push(location.1);
+                }
+            }
+        }
+
+        CompactFrames {
+            frames,
+            points,
+            locations,
+        }
+    }
+}
+
+impl FrameCache {
+    /// Positions of the entities at `frame`, simulating it if it is not cached.
+    pub fn get(&mut self, track: &Track, frame: usize) -> Vec<Entity> {
+        if let Some(entities) = self.frames.get(frame) {
+            self.hits += 1;
+            return entities.clone();
+        }
+        self.misses += 1;
+
+        if self.frames.is_empty() {
+            self.frames.push(track.entity_positions_at(0));
+        }
+        // frames are cached in order, so everything before `frame` gets filled in too
+        let mut entities = self.frames[self.frames.len() - 1].clone();
+        for next in self.frames.len()..=frame {
+            entities = step(track, &entities);
+            if next < MAX_CACHED_FRAMES {
+                self.frames.push(entities.clone());
+            }
+        }
+
+        entities
+    }
+
+    /// Adds frames that were simulated somewhere else, starting at frame `first`.
+    /// Frames that would leave a gap in the cache are ignored.
+    pub fn store(&mut self, first: usize, frames: Vec<Vec<Entity>>) {
+        for (frame, entities) in (first..).zip(frames) {
+            if frame == self.frames.len() && frame < MAX_CACHED_FRAMES {
+                self.frames.push(entities);
+            }
+        }
+    }
+
+    /// How many frames, starting from frame 0, are cached.
+    pub fn cached_frames(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// The last cached frame, which the next frame is simulated from.
+    pub fn last(&self) -> Option<&Vec<Entity>> {
+        self.frames.last()
+    }
+
+    /// Forgets every frame from the first one where an entity could have touched `line`.
+    pub fn invalidate_line(&mut self, line: &Line) {
+        if matches!(line.line_type, LineType::Scenery) {
+            return;
+        }
+
+        let (a, b) = (line.ends.0.location, line.ends.1.location);
+        let length = distance(a, b);
+        let margin = HITBOX_MARGIN + length * MAX_EXTENSION;
+        let min = Vector2D(a.0.min(b.0) - margin, a.1.min(b.1) - margin);
+        let max = Vector2D(a.0.max(b.0) + margin, a.1.max(b.1) + margin);
+
+        let first = self.frames.iter().position(|entities| {
+            entities.iter().any(|entity| {
+                entity.points.values().any(|point| {
+                    // the point could have passed through the line on its way here
+                    let speed = distance(point.location, point.previous_location);
+                    let location = point.location;
+                    location.0 >= min.0 - speed
+                        && location.0 <= max.0 + speed
+                        && location.1 >= min.1 - speed
+                        && location.1 <= max.1 + speed
+                })
+            })
+        });
+        if let Some(first) = first {
+            self.truncate(first);
+        }
+    }
+
+    /// Forgets every frame, ie when entities are added or the track is swapped out.
+    pub fn invalidate_all(&mut self) {
+        self.truncate(0);
+    }
+
+    fn truncate(&mut self, frame: usize) {
+        if frame < self.frames.len() {
+            self.frames.truncate(frame);
+            self.invalidations += 1;
+            self.last_invalidated_from = Some(frame);
+        }
+    }
+
+    pub fn stats(&self) -> FrameCacheStats {
+        FrameCacheStats {
+            cached_frames: self.frames.len(),
+            hits: self.hits,
+            misses: self.misses,
+            invalidations: self.invalidations,
+            last_invalidated_from: self.last_invalidated_from,
+        }
+    }
+}
+
+/// The frame after `entities`. The entities are all the state that the engine
+/// carries from one frame to the next, so stepping from a cached frame gives the
+/// same result as simulating from the start.
+pub fn step(track: &Track, entities: &[Entity]) -> Vec<Entity> {
+    frame_after(entities, track)
+}
+
+fn distance(a: Vector2D, b: Vector2D) -> f64 {
+    ((a.0 - b.0).powi(2) + (a.1 - b.1).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A rider with every point at `(x, 0)`, having moved `speed` to the right
+    /// since the previous frame.
+    fn rider_at(x: f64, speed: f64) -> Vec<Entity> {
+        let mut entity = Entity::default_boshsled();
+        for point in entity.points.values_mut() {
+            point.location = Vector2D(x, 0.0);
+            point.previous_location = Vector2D(x - speed, 0.0);
+        }
+
+        vec![entity]
+    }
+
+    /// A cache of 50 frames of a rider moving `speed` to the right every frame.
+    fn moving_rider(speed: f64) -> FrameCache {
+        let mut cache = FrameCache::default();
+        let frames = (0..50).map(|i| rider_at(i as f64 * speed, speed)).collect();
+        cache.store(0, frames);
+
+        cache
+    }
+
+    fn vertical_line(x: f64, half_height: f64) -> Line {
+        Line::builder()
+            .point(x, -half_height)
+            .point(x, half_height)
+            .build()
+    }
+
+    #[test]
+    fn far_away_lines_keep_the_cache() {
+        let mut cache = moving_rider(10.0);
+        cache.invalidate_line(&vertical_line(10_000.0, 50.0));
+
+        assert_eq!(cache.cached_frames(), 50);
+        assert_eq!(cache.stats().invalidations, 0);
+    }
+
+    #[test]
+    fn lines_in_the_path_truncate_the_cache() {
+        let mut cache = moving_rider(10.0);
+        // the line reaches 10 + 100 * 0.25 to each side, so it starts at x = 215,
+        // and the rider gets within one frame's movement of that on frame 21
+        cache.invalidate_line(&vertical_line(250.0, 50.0));
+
+        assert_eq!(cache.cached_frames(), 21);
+        assert_eq!(cache.stats().last_invalidated_from, Some(21));
+    }
+
+    #[test]
+    fn fast_points_passing_through_are_caught() {
+        let mut cache = moving_rider(100.0);
+        // no frame ends near the line, which the rider passes between x = 100
+        // and x = 200, so only its speed gives it away
+        cache.invalidate_line(&vertical_line(150.0, 5.0));
+
+        assert_eq!(cache.cached_frames(), 1);
+    }
+
+    #[test]
+    fn scenery_lines_keep_the_cache() {
+        let mut cache = moving_rider(10.0);
+        let mut line = vertical_line(250.0, 50.0);
+        line.line_type = LineType::Scenery;
+        cache.invalidate_line(&line);
+
+        assert_eq!(cache.cached_frames(), 50);
+    }
+}
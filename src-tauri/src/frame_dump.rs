@@ -0,0 +1,185 @@
+//! Dumps of simulated frames, for comparing physics between runs and against
+//! other Line Rider clients.
+
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use bosh_rs::rider::{Entity, PointIndex};
+use serde::Serialize;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// One row per point per frame, with the point's location and previous location.
+    Csv,
+    /// One JSON object per frame, holding the entities as `entity_positions_at` returns them.
+    Jsonl,
+}
+
+impl DumpFormat {
+    pub fn from_path(path: &Path) -> Option<DumpFormat> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(DumpFormat::Csv),
+            "jsonl" => Some(DumpFormat::Jsonl),
+            _ => None,
+        }
+    }
+}
+
+/// A single row of a csv dump.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpedPoint {
+    pub frame: usize,
+    /// index of the entity in the track
+    pub entity: usize,
+    pub point: String,
+    pub location: (f64, f64),
+    pub previous_location: (f64, f64),
+}
+
+#[derive(Serialize)]
+struct JsonlFrame<'a> {
+    frame: usize,
+    entities: &'a [Entity],
+}
+
+pub struct FrameWriter<W: Write> {
+    out: W,
+    format: DumpFormat,
+}
+
+impl<W: Write> FrameWriter<W> {
+    pub fn new(mut out: W, format: DumpFormat) -> Result<FrameWriter<W>> {
+        if format == DumpFormat::Csv {
+            writeln!(out, "frame,entity,point,x,y,previous_x,previous_y")
+                .context("error while writing csv header")?;
+        }
+
+        Ok(FrameWriter { out, format })
+    }
+
+    pub fn write_frame(&mut self, frame: usize, entities: &[Entity]) -> Result<()> {
+        match self.format {
+            DumpFormat::Csv => self.write_csv(frame, entities),
+            DumpFormat::Jsonl => {
+                serde_json::to_writer(&mut self.out, &JsonlFrame { frame, entities })
+                    .with_context(|| format!("error while writing frame {}", frame))?;
+                writeln!(self.out).with_context(|| format!("error while writing frame {}", frame))
+            }
+        }
+    }
+
+    fn write_csv(&mut self, frame: usize, entities: &[Entity]) -> Result<()> {
+        for point in dumped_points(frame, entities) {
+            writeln!(
+                self.out,
+                "{},{},{},{},{},{},{}",
+                point.frame,
+                point.entity,
+                point.point,
+                point.location.0,
+                point.location.1,
+                point.previous_location.0,
+                point.previous_location.1,
+            )
+            .with_context(|| format!("error while writing frame {}", frame))?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes everything that was written, handing back the writer.
+    pub fn finish(mut self) -> Result<W> {
+        self.out.flush().context("error while flushing frames")?;
+
+        Ok(self.out)
+    }
+}
+
+pub fn point_name(index: &PointIndex) -> String {
+    format!("{:?}", index)
+}
+
+/// Line Rider Advanced names, as in its `RiderConstants`, and the bosh-rs names
+/// of the same points.
+const LRA_POINT_NAMES: [(&str, &str); 10] = [
+    ("SledTL", "SledPeg"),
+    ("SledBL", "SledTail"),
+    ("SledBR", "SledNose"),
+    ("SledTR", "SledRope"),
+    ("BodyButt", "BoshButt"),
+    ("BodyShoulder", "BoshShoulder"),
+    ("BodyHandLeft", "BoshLeftHand"),
+    ("BodyHandRight", "BoshRightHand"),
+    ("BodyFootLeft", "BoshLeftFoot"),
+    ("BodyFootRight", "BoshRightFoot"),
+];
+
+/// The bosh-rs name of a point, so that dumps from Line Rider Advanced can use
+/// its own names. Any other name is kept as it is.
+pub fn bosh_point_name(name: &str) -> &str {
+    LRA_POINT_NAMES
+        .iter()
+        .find(|(lra, _)| *lra == name)
+        .map_or(name, |&(_, bosh)| bosh)
+}
+
+/// The rows that a frame is written as in a csv dump.
+pub fn dumped_points(frame: usize, entities: &[Entity]) -> Vec<DumpedPoint> {
+    let mut points = Vec::new();
+    for (entity_index, entity) in entities.iter().enumerate() {
+        let start = points.len();
+        points.extend(entity.points.iter().map(|(index, point)| DumpedPoint {
+            frame,
+            entity: entity_index,
+            point: point_name(index),
+            location: (point.location.0, point.location.1),
+            previous_location: (point.previous_location.0, point.previous_location.1),
+        }));
+        // sorted by name so that dumps of the same track always line up
+        points[start..].sort_by(|a, b| a.point.cmp(&b.point));
+    }
+
+    points
+}
+
+/// Reads a csv dump, like the ones written by `FrameWriter`. Points may be named
+/// the way Line Rider Advanced names them, see [`bosh_point_name`].
+pub fn read_csv<R: BufRead>(input: R) -> Result<Vec<DumpedPoint>> {
+    let mut points = Vec::new();
+    for (number, line) in input.lines().enumerate().skip(1) {
+        let line = line.context("error while reading csv dump")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let point =
+            parse_row(&line).with_context(|| format!("invalid row on line {}", number + 1))?;
+        points.push(point);
+    }
+
+    Ok(points)
+}
+
+fn parse_row(line: &str) -> Result<DumpedPoint> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    if fields.len() != 7 {
+        return Err(anyhow!("expected 7 fields, found {}", fields.len()));
+    }
+    let float = |index: usize| -> Result<f64> {
+        fields[index]
+            .parse()
+            .with_context(|| format!("{:?} is not a number", fields[index]))
+    };
+
+    Ok(DumpedPoint {
+        frame: fields[0]
+            .parse()
+            .with_context(|| format!("{:?} is not a frame", fields[0]))?,
+        entity: fields[1]
+            .parse()
+            .with_context(|| format!("{:?} is not an entity index", fields[1]))?,
+        point: bosh_point_name(fields[2]).to_string(),
+        location: (float(3)?, float(4)?),
+        previous_location: (float(5)?, float(6)?),
+    })
+}
@@ -0,0 +1,367 @@
+//! Undo/redo history of the edits made to a [`Document`](crate::document::Document).
+
+use std::collections::VecDeque;
+use std::mem::size_of;
+
+use anyhow::{anyhow, Result};
+use bosh::serialization::boshtf::{
+    BoshTFEntity, BoshTFLayer, BoshTFLine, BoshTFMeta, BoshTFTrack, BoshTFTrigger, RemovedLine,
+};
+use serde::Serialize;
+
+/// How many bytes of edits are kept around by default before the oldest ones are forgotten.
+pub const DEFAULT_BUDGET: usize = 64 * 1024 * 1024;
+
+/// A single reversible change to a document.
+#[derive(Debug, Clone)]
+pub enum Edit {
+    AddLine(RemovedLine),
+    RemoveLine(RemovedLine),
+    AddEntity {
+        index: usize,
+        entity: BoshTFEntity,
+    },
+    RemoveEntity {
+        index: usize,
+        entity: BoshTFEntity,
+    },
+    /// The whole track was swapped out, ie by clearing or loading a track.
+    ReplaceTrack {
+        before: Box<BoshTFTrack>,
+        after: Box<BoshTFTrack>,
+    },
+    SetMeta {
+        before: Box<BoshTFMeta>,
+        after: Box<BoshTFMeta>,
+    },
+    SetLayers {
+        before: Vec<BoshTFLayer>,
+        after: Vec<BoshTFLayer>,
+    },
+}
+
+impl Edit {
+    /// Rough number of bytes that keeping this edit around costs.
+    fn size(&self) -> usize {
+        let heap = match self {
+            Edit::AddLine(removed) | Edit::RemoveLine(removed) => {
+                removed.triggers.len() * size_of::<BoshTFTrigger>()
+            }
+            Edit::AddEntity { .. } | Edit::RemoveEntity { .. } => 0,
+            Edit::ReplaceTrack { before, after } => track_size(before) + track_size(after),
+            Edit::SetMeta { before, after } => meta_size(before) + meta_size(after),
+            Edit::SetLayers { before, after } => {
+                (before.len() + after.len()) * size_of::<BoshTFLayer>()
+            }
+        };
+
+        size_of::<Edit>() + heap
+    }
+}
+
+fn track_size(track: &BoshTFTrack) -> usize {
+    size_of::<BoshTFTrack>()
+        + meta_size(&track.meta)
+        + track.entities.len() * size_of::<BoshTFEntity>()
+        + track.lines.len() * size_of::<BoshTFLine>()
+        + track.triggers.len() * size_of::<BoshTFTrigger>()
+        + track.layers.len() * size_of::<BoshTFLayer>()
+}
+
+fn meta_size(meta: &BoshTFMeta) -> usize {
+    size_of::<BoshTFMeta>()
+        + meta
+            .lra_entries
+            .iter()
+            .map(|(key, value)| key.len() + value.len())
+            .sum::<usize>()
+}
+
+/// A group of edits that are undone and redone together.
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    pub label: String,
+    pub edits: Vec<Edit>,
+    size: usize,
+}
+
+impl Transaction {
+    fn new(label: String) -> Transaction {
+        Transaction {
+            label,
+            edits: vec![],
+            size: 0,
+        }
+    }
+
+    fn push(&mut self, edit: Edit) {
+        self.size += edit.size();
+        self.edits.push(edit);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct History {
+    undo: VecDeque<Transaction>,
+    redo: Vec<Transaction>,
+    /// transaction that edits are currently being grouped into
+    group: Option<Transaction>,
+    budget: usize,
+    used: usize,
+}
+
+/// What the frontend gets to see of the history.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct HistorySummary {
+    /// labels of the undoable transactions, oldest first
+    pub undo: Vec<String>,
+    /// labels of the redoable transactions, the next one to be redone last
+    pub redo: Vec<String>,
+    /// label of the group that edits are currently being added to
+    pub group: Option<String>,
+    pub bytes_used: usize,
+    pub byte_budget: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new(DEFAULT_BUDGET)
+    }
+}
+
+impl History {
+    pub fn new(budget: usize) -> History {
+        History {
+            undo: VecDeque::new(),
+            redo: vec![],
+            group: None,
+            budget,
+            used: 0,
+        }
+    }
+
+    /// Records an edit that was just made. Unless a group is open, the edit
+    /// gets its own transaction with the given label.
+    pub fn record(&mut self, label: &str, edit: Edit) {
+        self.record_all(label, vec![edit]);
+    }
+
+    /// Records edits that were just made, so that they are undone together.
+    pub fn record_all(&mut self, label: &str, edits: Vec<Edit>) {
+        if edits.is_empty() {
+            return;
+        }
+        self.used -= self.redo.drain(..).map(|t| t.size).sum::<usize>();
+
+        match &mut self.group {
+            Some(group) => edits.into_iter().for_each(|edit| group.push(edit)),
+            None => {
+                let mut transaction = Transaction::new(label.to_string());
+                edits.into_iter().for_each(|edit| transaction.push(edit));
+                self.push_undo(transaction);
+            }
+        }
+    }
+
+    /// Starts grouping all recorded edits into one transaction.
+    pub fn begin_group(&mut self, label: String) -> Result<()> {
+        if let Some(group) = &self.group {
+            return Err(anyhow!("already grouping edits into {:?}", group.label));
+        }
+        self.group = Some(Transaction::new(label));
+
+        Ok(())
+    }
+
+    /// Stops grouping edits, adding the group to the history if anything happened in it.
+    pub fn end_group(&mut self) -> Result<()> {
+        let group = self
+            .group
+            .take()
+            .ok_or_else(|| anyhow!("not grouping any edits"))?;
+        if !group.edits.is_empty() {
+            self.push_undo(group);
+        }
+
+        Ok(())
+    }
+
+    /// Takes the transaction that should be undone next. The caller is expected to
+    /// revert it and hand it back through [`History::push_redo`].
+    pub fn pop_undo(&mut self) -> Option<Transaction> {
+        if self.group.is_some() {
+            self.end_group().ok();
+        }
+        let transaction = self.undo.pop_back()?;
+        self.used -= transaction.size;
+
+        Some(transaction)
+    }
+
+    pub fn push_redo(&mut self, transaction: Transaction) {
+        self.used += transaction.size;
+        self.redo.push(transaction);
+    }
+
+    /// Takes the transaction that should be redone next. The caller is expected to
+    /// apply it and hand it back through [`History::push_undo`].
+    pub fn pop_redo(&mut self) -> Option<Transaction> {
+        if self.group.is_some() {
+            self.end_group().ok();
+        }
+        let transaction = self.redo.pop()?;
+        self.used -= transaction.size;
+
+        Some(transaction)
+    }
+
+    /// Adds a transaction to the undo stack, forgetting the oldest transactions if
+    /// the history is over budget. The newest transaction is always kept.
+    pub fn push_undo(&mut self, transaction: Transaction) {
+        self.used += transaction.size;
+        self.undo.push_back(transaction);
+
+        while self.used > self.budget && self.undo.len() > 1 {
+            if let Some(oldest) = self.undo.pop_front() {
+                self.used -= oldest.size;
+            }
+        }
+    }
+
+    pub fn summary(&self) -> HistorySummary {
+        HistorySummary {
+            undo: self.undo.iter().map(|t| t.label.clone()).collect(),
+            redo: self.redo.iter().map(|t| t.label.clone()).collect(),
+            group: self.group.as_ref().map(|t| t.label.clone()),
+            bytes_used: self.used,
+            byte_budget: self.budget,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An edit whose size does not depend on its contents.
+    fn edit() -> Edit {
+        Edit::SetLayers {
+            before: vec![],
+            after: vec![],
+        }
+    }
+
+    fn size() -> usize {
+        edit().size()
+    }
+
+    #[test]
+    fn recording_counts_bytes() {
+        let mut history = History::default();
+        history.record("a", edit());
+        history.record_all("b", vec![edit(), edit()]);
+        history.record_all("empty", vec![]);
+
+        let summary = history.summary();
+        assert_eq!(summary.undo, ["a", "b"]);
+        assert_eq!(summary.bytes_used, 3 * size());
+    }
+
+    #[test]
+    fn undo_and_redo_keep_bytes_balanced() {
+        let mut history = History::default();
+        history.record("a", edit());
+
+        let transaction = history.pop_undo().unwrap();
+        assert_eq!(history.summary().bytes_used, 0);
+        history.push_redo(transaction);
+        assert_eq!(history.summary().bytes_used, size());
+        assert_eq!(history.summary().redo, ["a"]);
+
+        let transaction = history.pop_redo().unwrap();
+        assert_eq!(history.summary().bytes_used, 0);
+        history.push_undo(transaction);
+        assert_eq!(history.summary().bytes_used, size());
+        assert_eq!(history.summary().undo, ["a"]);
+        assert!(history.pop_redo().is_none());
+    }
+
+    #[test]
+    fn recording_drains_redo() {
+        let mut history = History::default();
+        history.record("a", edit());
+        let transaction = history.pop_undo().unwrap();
+        history.push_redo(transaction);
+
+        history.record("b", edit());
+
+        let summary = history.summary();
+        assert_eq!(summary.undo, ["b"]);
+        assert!(summary.redo.is_empty());
+        assert_eq!(summary.bytes_used, size());
+    }
+
+    #[test]
+    fn groups_are_counted_once_ended() {
+        let mut history = History::default();
+        history.begin_group("drag".to_string()).unwrap();
+        assert!(history.begin_group("again".to_string()).is_err());
+        history.record("a", edit());
+        history.record("b", edit());
+
+        assert_eq!(history.summary().group.as_deref(), Some("drag"));
+        assert_eq!(history.summary().bytes_used, 0);
+
+        history.end_group().unwrap();
+        let summary = history.summary();
+        assert_eq!(summary.undo, ["drag"]);
+        assert_eq!(summary.group, None);
+        assert_eq!(summary.bytes_used, 2 * size());
+        assert!(history.end_group().is_err());
+    }
+
+    #[test]
+    fn empty_groups_are_not_recorded() {
+        let mut history = History::default();
+        history.begin_group("nothing".to_string()).unwrap();
+        history.end_group().unwrap();
+
+        assert!(history.summary().undo.is_empty());
+    }
+
+    #[test]
+    fn undo_ends_an_open_group() {
+        let mut history = History::default();
+        history.begin_group("drag".to_string()).unwrap();
+        history.record("a", edit());
+
+        let transaction = history.pop_undo().unwrap();
+        assert_eq!(transaction.label, "drag");
+        assert_eq!(history.summary().group, None);
+        assert_eq!(history.summary().bytes_used, 0);
+    }
+
+    #[test]
+    fn oldest_transactions_are_evicted_first() {
+        let mut history = History::new(2 * size());
+        history.record("a", edit());
+        history.record("b", edit());
+        history.record("c", edit());
+
+        let summary = history.summary();
+        assert_eq!(summary.undo, ["b", "c"]);
+        assert_eq!(summary.bytes_used, 2 * size());
+    }
+
+    #[test]
+    fn newest_transaction_is_kept_over_budget() {
+        let mut history = History::new(0);
+        history.record("a", edit());
+        history.record_all("b", vec![edit(), edit()]);
+
+        let summary = history.summary();
+        assert_eq!(summary.undo, ["b"]);
+        assert_eq!(summary.bytes_used, 2 * size());
+    }
+}
@@ -0,0 +1,6 @@
+//! Reading, writing and simulating tracks, shared by the app and `bosh-cli`.
+
+pub mod frame_dump;
+pub mod serialization;
+pub mod track_loading;
+pub mod track_saving;
@@ -3,100 +3,657 @@
     windows_subsystem = "windows"
 )]
 
-use std::sync::Mutex;
+use std::collections::HashSet;
+use std::sync::Arc;
 
+use bosh::serialization::boshtf::{
+    BoshTFEntity, BoshTFLayer, BoshTFLine, BoshTFMeta, BoshTFTrack, BoshTFTrigger,
+};
+use bosh::serialization::{ConversionWarning, TrackFormat};
+use bosh::track_loading::LoadedTrack;
+use bosh::{track_loading, track_saving};
 use bosh_rs::rider::Entity;
-use bosh_rs::{Line, Track};
-use once_cell::sync::Lazy;
-use tauri::command;
+use bosh_rs::{Line, Vector2D};
+use serde::Serialize;
+use tauri::{command, Manager, State, Window, WindowEvent};
 
-use crate::serialization::boshtf::{BoshTFEntity, BoshTFTrack};
+use crate::contacts::LineContacts;
+use crate::document::{LineChanges, LineDelta};
+use crate::documents::{lock_document, DocumentId, DocumentInfo, Documents};
+use crate::events::RiderEvent;
+use crate::frame_cache::{CompactFrames, FrameCacheStats};
+use crate::history::HistorySummary;
+use crate::simulation::SimulationMessage;
 
-mod serialization;
-mod track_loading;
-
-static TRACK: Lazy<Mutex<Track>> = Lazy::new(|| Mutex::new(Track::new(vec![], vec![])));
+mod contacts;
+mod document;
+mod documents;
+mod events;
+mod frame_cache;
+mod history;
+mod simulation;
+mod spatial;
 
 fn main() {
     tauri::Builder::default()
+        .manage(Documents::default())
+        .on_window_event(|event| {
+            if let WindowEvent::Destroyed = event.event() {
+                let window = event.window();
+                window.state::<Documents>().close_window(window.label());
+            }
+        })
         .invoke_handler(tauri::generate_handler![
+            open_document,
+            select_document,
+            close_document,
+            list_documents,
+            copy_lines,
             clear,
             add_line,
             remove_line,
+            add_lines,
+            remove_lines,
+            replace_lines,
+            lines_since,
+            lines_in_rect,
+            lines_near_point,
             add_entity,
             remove_entity,
             entity_positions_at,
+            entity_positions_range,
+            compact_positions_range,
+            find_events,
+            line_contacts,
+            frame_cache_stats,
+            simulate_ahead,
+            cancel_simulation,
             load_track,
+            save_track,
+            get_track_meta,
+            set_track_meta,
+            get_layers,
+            create_layer,
+            rename_layer,
+            set_layer_visible,
+            set_layer_editable,
+            move_layer,
+            undo,
+            redo,
+            history,
+            begin_edit_group,
+            end_edit_group,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
 
 #[command]
-fn add_line(line: Line) -> Result<Vec<Line>, String> {
-    let mut track = TRACK.lock().map_err(|err| err.to_string())?;
+fn add_line(
+    window: Window,
+    documents: State<'_, Documents>,
+    line: Line,
+    layer: Option<u64>,
+) -> Result<LineChanges, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document
+        .add_line(line, layer)
+        .map_err(|err| err.to_string())
+}
+
+#[command]
+fn remove_line(
+    window: Window,
+    documents: State<'_, Documents>,
+    id: u64,
+) -> Result<LineChanges, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document.remove_line(id).map_err(|err| err.to_string())
+}
+
+#[command]
+fn add_lines(
+    window: Window,
+    documents: State<'_, Documents>,
+    lines: Vec<Line>,
+    layer: Option<u64>,
+) -> Result<LineChanges, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document
+        .add_lines(lines, layer)
+        .map_err(|err| err.to_string())
+}
+
+#[command]
+fn remove_lines(
+    window: Window,
+    documents: State<'_, Documents>,
+    ids: Vec<u64>,
+) -> Result<LineChanges, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document.remove_lines(&ids).map_err(|err| err.to_string())
+}
+
+#[command]
+fn replace_lines(
+    window: Window,
+    documents: State<'_, Documents>,
+    lines: Vec<BoshTFLine>,
+) -> Result<LineChanges, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document.replace_lines(lines).map_err(|err| err.to_string())
+}
+
+/// Everything that happened to the lines after `revision`, for catching up on edits.
+/// Revisions are counted per document, so if the window has switched away from
+/// `document` since, every line of the active document is sent instead.
+#[command]
+fn lines_since(
+    window: Window,
+    documents: State<'_, Documents>,
+    document: Option<DocumentId>,
+    revision: u64,
+) -> Result<LineDelta, String> {
+    let (id, active) = documents.active(window.label());
+    let active = lock_document(&active);
+
+    let mut delta = if document == Some(id) {
+        active.lines_since(revision)
+    } else {
+        active.all_lines()
+    };
+    delta.document = id;
+
+    Ok(delta)
+}
+
+#[command]
+fn lines_in_rect(
+    window: Window,
+    documents: State<'_, Documents>,
+    min: Vector2D,
+    max: Vector2D,
+) -> Result<Vec<BoshTFLine>, String> {
+    let (_, document) = documents.active(window.label());
+    let document = lock_document(&document);
+
+    Ok(document.lines_in_rect(min, max))
+}
 
-    track.add_line(line);
+/// Lines within `radius` of `point`, for hit-testing tools like the eraser.
+#[command]
+fn lines_near_point(
+    window: Window,
+    documents: State<'_, Documents>,
+    point: Vector2D,
+    radius: f64,
+) -> Result<Vec<BoshTFLine>, String> {
+    let (_, document) = documents.active(window.label());
+    let document = lock_document(&document);
 
-    Ok(track.all_lines().clone())
+    Ok(document.lines_near_point(point, radius))
 }
 
 #[command]
-fn remove_line(line: Line) -> Result<Vec<Line>, String> {
-    let mut track = TRACK.lock().map_err(|err| err.to_string())?;
+fn get_layers(window: Window, documents: State<'_, Documents>) -> Result<Vec<BoshTFLayer>, String> {
+    let (_, document) = documents.active(window.label());
+    let document = lock_document(&document);
 
-    track.remove_line(&line);
+    Ok(document.extras().layers.clone())
+}
+
+#[command]
+fn create_layer(
+    window: Window,
+    documents: State<'_, Documents>,
+    name: String,
+    folder: Option<u64>,
+) -> Result<BoshTFLayer, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
 
-    Ok(track.all_lines().clone())
+    document
+        .edit_layers("create layer", |extras| {
+            extras.create_layer(name, folder).map(|layer| layer.clone())
+        })
+        .map_err(|err| err.to_string())
 }
 
 #[command]
-fn add_entity(entity: BoshTFEntity) -> Result<(), String> {
-    let entity: Entity = (&entity).into();
-    let mut track = TRACK.lock().map_err(|err| err.to_string())?;
+fn rename_layer(
+    window: Window,
+    documents: State<'_, Documents>,
+    id: u64,
+    name: String,
+) -> Result<Vec<BoshTFLayer>, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
 
-    track.create_entity(entity);
+    document
+        .edit_layers("rename layer", |extras| {
+            extras.layer_mut(id)?.name = name;
+            Ok(())
+        })
+        .map_err(|err| err.to_string())?;
+
+    Ok(document.extras().layers.clone())
+}
+
+#[command]
+fn set_layer_visible(
+    window: Window,
+    documents: State<'_, Documents>,
+    id: u64,
+    visible: bool,
+) -> Result<Vec<BoshTFLayer>, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document
+        .edit_layers("change layer visibility", |extras| {
+            extras.layer_mut(id)?.visible = visible;
+            Ok(())
+        })
+        .map_err(|err| err.to_string())?;
+
+    Ok(document.extras().layers.clone())
+}
+
+#[command]
+fn set_layer_editable(
+    window: Window,
+    documents: State<'_, Documents>,
+    id: u64,
+    editable: bool,
+) -> Result<Vec<BoshTFLayer>, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document
+        .edit_layers("lock layer", |extras| {
+            extras.layer_mut(id)?.editable = editable;
+            Ok(())
+        })
+        .map_err(|err| err.to_string())?;
+
+    Ok(document.extras().layers.clone())
+}
+
+#[command]
+fn move_layer(
+    window: Window,
+    documents: State<'_, Documents>,
+    id: u64,
+    index: usize,
+) -> Result<Vec<BoshTFLayer>, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document
+        .edit_layers("move layer", |extras| extras.move_layer(id, index))
+        .map_err(|err| err.to_string())?;
+
+    Ok(document.extras().layers.clone())
+}
+
+#[command]
+fn add_entity(
+    window: Window,
+    documents: State<'_, Documents>,
+    entity: BoshTFEntity,
+) -> Result<(), String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document.add_entity(entity);
 
     Ok(())
 }
 
 #[command]
-fn remove_entity(entity: BoshTFEntity) -> Result<(), String> {
-    let entity: Entity = (&entity).into();
-    let mut track = TRACK.lock().map_err(|err| err.to_string())?;
+fn remove_entity(
+    window: Window,
+    documents: State<'_, Documents>,
+    entity: BoshTFEntity,
+) -> Result<(), String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
 
-    track.remove_entity(entity);
+    document.remove_entity(&entity);
 
     Ok(())
 }
 
 #[command]
-fn entity_positions_at(frame: usize) -> Result<Vec<Entity>, String> {
-    let serialized_positions = TRACK
-        .lock()
-        .map_err(|err| {
-            eprintln!("{}", err);
-            err.to_string()
-        })?
-        .entity_positions_at(frame);
+fn entity_positions_at(
+    window: Window,
+    documents: State<'_, Documents>,
+    frame: usize,
+) -> Result<Vec<Entity>, String> {
+    let (_, document) = documents.active(window.label());
+    let serialized_positions = lock_document(&document).entity_positions_at(frame);
 
     Ok(serialized_positions)
 }
 
+/// Positions of the entities at every `step`th frame in `start..end`, so that
+/// playback does not need a call for every frame.
 #[command]
-fn load_track(path: String) -> Result<BoshTFTrack, String> {
-    let track = track_loading::load(&path).map_err(|err| {
+fn entity_positions_range(
+    window: Window,
+    documents: State<'_, Documents>,
+    start: usize,
+    end: usize,
+    step: usize,
+) -> Result<Vec<Vec<Entity>>, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document
+        .entity_positions_range(start, end, step)
+        .map_err(|err| err.to_string())
+}
+
+/// Same as [`entity_positions_range`], but only point locations packed into one array.
+#[command]
+fn compact_positions_range(
+    window: Window,
+    documents: State<'_, Documents>,
+    start: usize,
+    end: usize,
+    step: usize,
+) -> Result<CompactFrames, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document
+        .compact_positions_range(start, end, step)
+        .map_err(|err| err.to_string())
+}
+
+/// Events in `start..end`, like where the rider dismounts, so that the timeline
+/// can mark them. Nothing leaves the screen unless `screen` is given as `(min, max)`.
+#[command]
+fn find_events(
+    window: Window,
+    documents: State<'_, Documents>,
+    start: usize,
+    end: usize,
+    screen: Option<(Vector2D, Vector2D)>,
+) -> Result<Vec<RiderEvent>, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document
+        .find_events(start, end, screen)
+        .map_err(|err| err.to_string())
+}
+
+/// Lines that each point touches on every frame in `start..end`, and every line
+/// touched at all, so that used lines can be highlighted.
+#[command]
+fn line_contacts(
+    window: Window,
+    documents: State<'_, Documents>,
+    start: usize,
+    end: usize,
+) -> Result<LineContacts, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document
+        .line_contacts(start, end)
+        .map_err(|err| err.to_string())
+}
+
+/// Simulates `frames` frames past `playhead` in the background, reporting
+/// progress through the `simulated_up_to` event.
+#[command]
+fn simulate_ahead(
+    window: Window,
+    documents: State<'_, Documents>,
+    playhead: usize,
+    frames: usize,
+) -> Result<(), String> {
+    let (id, shared) = documents.active(window.label());
+    let mut document = lock_document(&shared);
+
+    if !document.has_simulation() {
+        let simulation = simulation::spawn(window.clone(), id, Arc::downgrade(&shared));
+        document.set_simulation(simulation);
+    }
+    document.send_simulation(SimulationMessage::SimulateTo(
+        playhead.saturating_add(frames),
+    ));
+
+    Ok(())
+}
+
+#[command]
+fn cancel_simulation(window: Window, documents: State<'_, Documents>) -> Result<(), String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document.send_simulation(SimulationMessage::Cancel);
+
+    Ok(())
+}
+
+#[command]
+fn frame_cache_stats(
+    window: Window,
+    documents: State<'_, Documents>,
+) -> Result<FrameCacheStats, String> {
+    let (_, document) = documents.active(window.label());
+    let document = lock_document(&document);
+
+    Ok(document.frame_cache_stats())
+}
+
+/// A loaded track, along with the document it was loaded into.
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct LoadTrackResponse {
+    #[serde(flatten)]
+    loaded: LoadedTrack,
+    document: DocumentId,
+    /// revision of the document once the track has been loaded into it
+    revision: u64,
+}
+
+#[command]
+fn load_track(
+    window: Window,
+    documents: State<'_, Documents>,
+    path: String,
+) -> Result<LoadTrackResponse, String> {
+    let loaded = track_loading::load(&path).map_err(|err| {
         eprintln!("{:#}", err);
         err.to_string()
     })?;
-    *TRACK.lock().map_err(|err| err.to_string())? = (&track).into();
+    let (id, document) = documents.active(window.label());
+    let revision = lock_document(&document)
+        .replace_track("load track", &loaded.track)
+        .revision;
+
+    Ok(LoadTrackResponse {
+        loaded,
+        document: id,
+        revision,
+    })
+}
+
+#[command]
+fn save_track(
+    window: Window,
+    documents: State<'_, Documents>,
+    path: String,
+    format: TrackFormat,
+) -> Result<Vec<ConversionWarning>, String> {
+    let track = {
+        let (_, document) = documents.active(window.label());
+        let document = lock_document(&document);
+
+        BoshTFTrack::from(document.extras())
+    };
+
+    track_saving::save(&path, format, &track).map_err(|err| {
+        eprintln!("{:#}", err);
+        err.to_string()
+    })
+}
+
+#[command]
+fn get_track_meta(window: Window, documents: State<'_, Documents>) -> Result<BoshTFMeta, String> {
+    let (_, document) = documents.active(window.label());
+    let document = lock_document(&document);
+
+    Ok(document.extras().meta.clone())
+}
+
+#[command]
+fn set_track_meta(
+    window: Window,
+    documents: State<'_, Documents>,
+    meta: BoshTFMeta,
+) -> Result<(), String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document.set_meta(meta);
+
+    Ok(())
+}
+
+/// Undoes the last edit, returning the lines that changed.
+#[command]
+fn undo(window: Window, documents: State<'_, Documents>) -> Result<LineChanges, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document.undo().map_err(|err| err.to_string())
+}
+
+/// Redoes the last undone edit, returning the lines that changed.
+#[command]
+fn redo(window: Window, documents: State<'_, Documents>) -> Result<LineChanges, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document.redo().map_err(|err| err.to_string())
+}
+
+#[command]
+fn history(window: Window, documents: State<'_, Documents>) -> Result<HistorySummary, String> {
+    let (_, document) = documents.active(window.label());
+    let document = lock_document(&document);
 
-    Ok(track)
+    Ok(document.history())
 }
 
+/// Groups all edits until [`end_edit_group`] into a single undo step.
 #[command]
-fn clear() {
-    let mut track = TRACK.lock().unwrap();
+fn begin_edit_group(
+    window: Window,
+    documents: State<'_, Documents>,
+    label: String,
+) -> Result<(), String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document.begin_group(label).map_err(|err| err.to_string())
+}
+
+#[command]
+fn end_edit_group(window: Window, documents: State<'_, Documents>) -> Result<(), String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    document.end_group().map_err(|err| err.to_string())
+}
+
+#[command]
+fn clear(window: Window, documents: State<'_, Documents>) -> Result<LineChanges, String> {
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
+
+    Ok(document.replace_track("clear track", &BoshTFTrack::default()))
+}
+
+/// Opens an empty document and shows it in this window, returning its id.
+#[command]
+fn open_document(window: Window, documents: State<'_, Documents>) -> DocumentId {
+    documents.open(window.label())
+}
+
+/// Shows another open document in this window.
+#[command]
+fn select_document(
+    window: Window,
+    documents: State<'_, Documents>,
+    id: DocumentId,
+) -> Result<(), String> {
+    documents
+        .select(window.label(), id)
+        .map_err(|err| err.to_string())
+}
+
+#[command]
+fn close_document(documents: State<'_, Documents>, id: DocumentId) -> Result<(), String> {
+    documents.close(id).map_err(|err| err.to_string())
+}
+
+#[command]
+fn list_documents(window: Window, documents: State<'_, Documents>) -> Vec<DocumentInfo> {
+    documents.list(window.label())
+}
+
+/// Copies lines, along with how they are drawn and their triggers, from another
+/// document into the one shown in this window, as a single edit.
+#[command]
+fn copy_lines(
+    window: Window,
+    documents: State<'_, Documents>,
+    from: DocumentId,
+    ids: Vec<u64>,
+    layer: Option<u64>,
+) -> Result<LineChanges, String> {
+    let copies: Vec<(BoshTFLine, Vec<BoshTFTrigger>)> = {
+        let source = documents.get(from).map_err(|err| err.to_string())?;
+        let source = lock_document(&source);
+        let extras = source.extras();
+        let ids: HashSet<u64> = ids.into_iter().collect();
+
+        extras
+            .lines
+            .iter()
+            .filter_map(|l| {
+                let id = l.id.filter(|id| ids.contains(id))?;
+                let triggers = extras
+                    .triggers
+                    .iter()
+                    .filter(|trigger| trigger.line() == id)
+                    .cloned()
+                    .collect();
+                Some((l.clone(), triggers))
+            })
+            .collect()
+    };
+
+    let (_, document) = documents.active(window.label());
+    let mut document = lock_document(&document);
 
-    *track = Track::new(vec![], vec![]);
+    document
+        .paste_lines(copies, layer)
+        .map_err(|err| err.to_string())
 }
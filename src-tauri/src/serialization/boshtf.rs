@@ -1,13 +1,32 @@
 //! Module for deserializing BoshTF, the Bosh Track Format
 
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Context, Result};
 use bosh_rs::rider::{Entity, PointIndex};
 use bosh_rs::{Line, LineType, Track, TrackMeta, Vector2D};
 use serde::{Deserialize, Serialize};
 
-pub type BoshTFLine = Line;
 pub type BoshTFLineType = LineType;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoshTFLine {
+    /// Stable identifier of the line, assigned when loading if missing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<u64>,
+    /// How thick the line is drawn, relative to the default thickness.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<BoshTFColor>,
+    /// id of the layer that the line is drawn on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub layer: Option<u64>,
+    #[serde(flatten)]
+    pub line: Line,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum BoshTFEntity {
     #[serde(rename = "boshSled")]
     BoshSled {
@@ -15,6 +34,12 @@ pub enum BoshTFEntity {
         velocity: Vector2D,
         #[serde(default = "default_starting_position")]
         position: Vector2D,
+        /// whether the rider can get back on the sled after falling off
+        #[serde(default)]
+        remountable: bool,
+        /// whether the rider slides along lines without any friction
+        #[serde(default)]
+        frictionless: bool,
     },
     #[serde(rename = "custom")]
     Custom(Entity),
@@ -29,18 +54,346 @@ fn default_starting_velocity() -> Vector2D {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BoshTFTrigger {
+    /// Zooms the camera to `target` over `frames` frames when `line` is hit.
+    #[serde(rename = "zoom")]
+    Zoom {
+        /// id of the line that activates the trigger
+        line: u64,
+        target: f32,
+        frames: i16,
+    },
+}
+
+impl BoshTFTrigger {
+    pub fn line(&self) -> u64 {
+        match self {
+            BoshTFTrigger::Zoom { line, .. } => *line,
+        }
+    }
+
+    /// Moves the trigger onto another line.
+    pub fn set_line(&mut self, id: u64) {
+        match self {
+            BoshTFTrigger::Zoom { line, .. } => *line = id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BoshTFSong {
+    pub name: String,
+    #[serde(default)]
+    pub offset_seconds: f64,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BoshTFColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BoshTFMeta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub song: Option<BoshTFSong>,
+    /// length of the track, in frames
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duration: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_zoom: Option<f32>,
+    /// multiplier applied to gravity on each axis
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gravity_scale: Option<Vector2D>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gravity_well_size: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub background_color: Option<BoshTFColor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub line_color: Option<BoshTFColor>,
+    /// metadata entries from LRA that bosh does not understand, kept so they can be written back
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub lra_entries: BTreeMap<String, String>,
+
+    #[serde(flatten)]
+    pub track: TrackMeta,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BoshTFLayer {
+    pub id: u64,
+    pub name: String,
+    #[serde(default = "default_true")]
+    pub visible: bool,
+    /// whether lines on this layer can be edited
+    #[serde(default = "default_true")]
+    pub editable: bool,
+    /// id of the folder that this layer is inside of
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub folder: Option<u64>,
+    /// whether this is a folder that groups other layers, rather than a layer with lines
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub is_folder: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct BoshTFTrack {
     #[serde(default)]
-    pub meta: TrackMeta,
+    pub meta: BoshTFMeta,
+    pub entities: Vec<BoshTFEntity>,
+    pub lines: Vec<BoshTFLine>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub triggers: Vec<BoshTFTrigger>,
+    /// Layers in the order that they are drawn, from bottom to top.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub layers: Vec<BoshTFLayer>,
+}
+
+/// Track data that the physics engine does not keep track of.
+#[derive(Debug, Clone, Default)]
+pub struct TrackExtras {
+    pub meta: BoshTFMeta,
+    /// Entities as they were added, since settings like `remountable` are lost
+    /// once they become physics entities.
     pub entities: Vec<BoshTFEntity>,
+    /// Lines in the order they were added to the track, along with their ids.
     pub lines: Vec<BoshTFLine>,
+    pub next_line_id: u64,
+    pub triggers: Vec<BoshTFTrigger>,
+    pub layers: Vec<BoshTFLayer>,
+}
+
+impl TrackExtras {
+    /// Registers a new line, returning its newly allocated id.
+    pub fn add_line(&mut self, line: Line, layer: Option<u64>) -> Result<u64> {
+        self.check_line_layer(layer)?;
+
+        let id = self.next_line_id;
+        self.next_line_id += 1;
+        self.lines.push(BoshTFLine {
+            id: Some(id),
+            width: None,
+            color: None,
+            layer,
+            line,
+        });
+
+        Ok(id)
+    }
+
+    /// Makes sure that lines on `layer` can be added, changed or removed.
+    pub fn check_line_layer(&self, layer: Option<u64>) -> Result<()> {
+        if let Some(layer) = layer {
+            let layer = self.layer(layer)?;
+            if layer.is_folder {
+                return Err(anyhow!("folder {} cannot hold lines", layer.id));
+            }
+            if !layer.editable {
+                return Err(anyhow!("layer {} is locked", layer.id));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn layer(&self, id: u64) -> Result<&BoshTFLayer> {
+        self.layers
+            .iter()
+            .find(|layer| layer.id == id)
+            .with_context(|| format!("no layer with id {id}"))
+    }
+
+    pub fn layer_mut(&mut self, id: u64) -> Result<&mut BoshTFLayer> {
+        self.layers
+            .iter_mut()
+            .find(|layer| layer.id == id)
+            .with_context(|| format!("no layer with id {id}"))
+    }
+
+    /// Creates a new layer on top of all other layers.
+    pub fn create_layer(&mut self, name: String, folder: Option<u64>) -> Result<&BoshTFLayer> {
+        if let Some(folder) = folder {
+            if !self.layer(folder)?.is_folder {
+                return Err(anyhow!("layer {folder} is not a folder"));
+            }
+        }
+
+        let id = self.layers.iter().map(|l| l.id + 1).max().unwrap_or(0);
+        self.layers.push(BoshTFLayer {
+            id,
+            name,
+            visible: true,
+            editable: true,
+            folder,
+            is_folder: false,
+        });
+
+        self.layer(id)
+    }
+
+    /// Moves a layer so that it is drawn at `index`, counting from the bottom.
+    pub fn move_layer(&mut self, id: u64, index: usize) -> Result<()> {
+        let from = self
+            .layers
+            .iter()
+            .position(|layer| layer.id == id)
+            .with_context(|| format!("no layer with id {id}"))?;
+        if index >= self.layers.len() {
+            return Err(anyhow!("layer index {index} is out of bounds"));
+        }
+
+        let layer = self.layers.remove(from);
+        self.layers.insert(index, layer);
+
+        Ok(())
+    }
+
+    /// Removes the first entity that is the same as `entity`, or failing that the
+    /// first one that starts out the same, returning where it was and what it was.
+    pub fn remove_entity(&mut self, entity: &BoshTFEntity) -> Option<(usize, BoshTFEntity)> {
+        let physics_entity = Entity::from(entity);
+        let index = self.entities.iter().position(|e| e == entity).or_else(|| {
+            self.entities
+                .iter()
+                .position(|e| Entity::from(e) == physics_entity)
+        })?;
+
+        Some((index, self.entities.remove(index)))
+    }
+
+    /// Converts a physics entity back, taking settings that physics entities do
+    /// not carry, like `remountable`, from the first entity that starts out the same.
+    pub fn entity_from(&self, entity: &Entity) -> BoshTFEntity {
+        let mut converted = BoshTFEntity::from(entity);
+        let original = self.entities.iter().find(|e| &Entity::from(*e) == entity);
+        if let (
+            BoshTFEntity::BoshSled { remountable, .. },
+            Some(BoshTFEntity::BoshSled {
+                remountable: original,
+                ..
+            }),
+        ) = (&mut converted, original)
+        {
+            *remountable = *original;
+        }
+
+        converted
+    }
+
+    /// Removes the line with the given id, along with any triggers attached to it.
+    pub fn remove_line(&mut self, id: u64) -> Result<Option<RemovedLine>> {
+        let index = match self.lines.iter().position(|l| l.id == Some(id)) {
+            Some(index) => index,
+            None => return Ok(None),
+        };
+        self.check_line_layer(self.lines[index].layer)?;
+
+        Ok(Some(self.take_line(index)))
+    }
+
+    /// Removes the line at `index` and its triggers, without checking its layer.
+    pub fn take_line(&mut self, index: usize) -> RemovedLine {
+        let line = self.lines.remove(index);
+        let (triggers, kept) = self
+            .triggers
+            .drain(..)
+            .partition(|trigger| Some(trigger.line()) == line.id);
+        self.triggers = kept;
+
+        RemovedLine {
+            index,
+            line,
+            triggers,
+        }
+    }
+
+    /// Puts a line removed by [`TrackExtras::remove_line`] back where it was.
+    pub fn restore_line(&mut self, removed: RemovedLine) {
+        let index = removed.index.min(self.lines.len());
+        self.lines.insert(index, removed.line);
+        self.triggers.extend(removed.triggers);
+    }
+}
+
+/// A line taken out of [`TrackExtras`], with everything needed to put it back.
+#[derive(Debug, Clone)]
+pub struct RemovedLine {
+    /// where the line was in [`TrackExtras::lines`]
+    pub index: usize,
+    pub line: BoshTFLine,
+    /// triggers that were attached to the line
+    pub triggers: Vec<BoshTFTrigger>,
+}
+
+impl From<&BoshTFTrack> for TrackExtras {
+    fn from(track: &BoshTFTrack) -> TrackExtras {
+        let mut track = track.clone();
+        track.assign_line_ids();
+
+        TrackExtras {
+            meta: track.meta,
+            entities: track.entities,
+            next_line_id: track.next_line_id(),
+            lines: track.lines,
+            triggers: track.triggers,
+            layers: track.layers,
+        }
+    }
+}
+
+impl From<&TrackExtras> for BoshTFTrack {
+    fn from(extras: &TrackExtras) -> BoshTFTrack {
+        BoshTFTrack {
+            meta: extras.meta.clone(),
+            entities: extras.entities.clone(),
+            lines: extras.lines.clone(),
+            triggers: extras.triggers.clone(),
+            layers: extras.layers.clone(),
+        }
+    }
+}
+
+impl BoshTFTrack {
+    /// The smallest id that is greater than the id of every line in the track.
+    pub fn next_line_id(&self) -> u64 {
+        self.lines
+            .iter()
+            .filter_map(|l| l.id)
+            .max()
+            .map_or(0, |id| id + 1)
+    }
+
+    /// Gives every line without an id a new, unique id.
+    pub fn assign_line_ids(&mut self) {
+        let mut next_line_id = self.next_line_id();
+        for line in self.lines.iter_mut().filter(|l| l.id.is_none()) {
+            line.id = Some(next_line_id);
+            next_line_id += 1;
+        }
+    }
 }
 
 impl From<&BoshTFTrack> for Track {
     fn from(track: &BoshTFTrack) -> Track {
+        // bosh-rs does not accept gravity overrides when building a track, so
+        // `gravity_scale` and `gravity_well_size` are only carried through saving
         Track::new(
             track.entities.iter().map(Entity::from).collect(),
-            track.lines.clone(),
+            track.lines.iter().map(|l| l.line.clone()).collect(),
         )
     }
 }
@@ -48,11 +401,20 @@ impl From<&BoshTFTrack> for Track {
 impl From<&BoshTFEntity> for Entity {
     fn from(entity: &BoshTFEntity) -> Self {
         match entity {
-            BoshTFEntity::BoshSled { position, velocity } => {
+            // bosh-rs has no remounting yet, so `remountable` cannot be forwarded
+            BoshTFEntity::BoshSled {
+                position,
+                velocity,
+                frictionless,
+                ..
+            } => {
                 let mut bosh_sled = Entity::default_boshsled();
                 bosh_sled.mutate_points(|point| {
                     point.location += *position;
                     point.previous_location = point.location - *velocity;
+                    if *frictionless {
+                        point.friction = 0.0;
+                    }
                 });
 
                 bosh_sled
@@ -62,6 +424,8 @@ impl From<&BoshTFEntity> for Entity {
     }
 }
 
+/// Physics entities cannot tell whether they are remountable, so riders always come
+/// out as not remountable. [`TrackExtras::entity_from`] keeps the flag.
 impl From<&Entity> for BoshTFEntity {
     fn from(entity: &Entity) -> Self {
         let peg = entity.points.get(&PointIndex::SledPeg);
@@ -73,8 +437,9 @@ impl From<&Entity> for BoshTFEntity {
         let peg_velocity = peg.previous_location - peg.location;
 
         let default_boshsled: Entity = Entity::default_boshsled();
+        let frictionless = entity.points.values().all(|p| p.friction == 0.0);
         let mut mapped_entity = entity.clone();
-        for p in mapped_entity.points.values_mut() {
+        for (index, p) in mapped_entity.points.iter_mut() {
             let p_velocity = p.previous_location - p.location;
             if p_velocity != peg_velocity {
                 return BoshTFEntity::Custom(entity.clone());
@@ -82,6 +447,11 @@ impl From<&Entity> for BoshTFEntity {
 
             p.location -= peg_location;
             p.previous_location -= peg_location;
+            if frictionless {
+                if let Some(default_point) = default_boshsled.points.get(index) {
+                    p.friction = default_point.friction;
+                }
+            }
         }
 
         if default_boshsled != mapped_entity {
@@ -90,6 +460,8 @@ impl From<&Entity> for BoshTFEntity {
             BoshTFEntity::BoshSled {
                 velocity: peg_velocity,
                 position: peg_location,
+                remountable: false,
+                frictionless,
             }
         }
     }
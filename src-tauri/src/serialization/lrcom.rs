@@ -1,12 +1,17 @@
 //! Module for deserializing linerider.com tracks, aka ".track.json"
 
 use std::borrow::Borrow;
+use std::collections::HashMap;
 
 use anyhow::{anyhow, Context, Error};
 use bosh_rs::{Line, Vector2D};
 use serde::{Deserialize, Serialize};
 
-use crate::serialization::boshtf::{BoshTFEntity, BoshTFLine, BoshTFLineType, BoshTFTrack};
+use crate::serialization::boshtf::{
+    BoshTFColor, BoshTFEntity, BoshTFLayer, BoshTFLine, BoshTFLineType, BoshTFMeta, BoshTFSong,
+    BoshTFTrack,
+};
+use crate::serialization::ConversionWarning;
 
 type Result<T> = anyhow::Result<T>;
 
@@ -29,6 +34,8 @@ pub struct LRComTrack {
     start_position: LRComVec2,
     #[serde(skip_serializing_if = "Option::is_none")]
     riders: Option<Vec<LRComEntity>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    layers: Option<Vec<LRComLayer>>,
 
     #[serde(default)]
     lines: Option<Vec<LRComLine>>,
@@ -42,9 +49,61 @@ pub struct LRComEntity {
     start_position: LRComVec2,
     #[serde(rename = "startVelocity")]
     start_velocity: LRComVec2,
+    #[serde(default)]
     remountable: u8, // ... why is this not a boolean? are there more values?
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LRComLayer {
+    id: u64,
+    name: String,
+    #[serde(default = "default_true")]
+    visible: bool,
+    #[serde(default = "default_true")]
+    editable: bool,
+    #[serde(rename = "folderId", default, skip_serializing_if = "Option::is_none")]
+    folder_id: Option<u64>,
+    /// 1 for folders, missing for regular layers
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    layer_type: Option<u8>,
+    /// number of layers inside a folder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+const LRCOM_FOLDER_TYPE: u8 = 1;
+
+impl From<&LRComLayer> for BoshTFLayer {
+    fn from(layer: &LRComLayer) -> BoshTFLayer {
+        BoshTFLayer {
+            id: layer.id,
+            name: layer.name.clone(),
+            visible: layer.visible,
+            editable: layer.editable,
+            folder: layer.folder_id,
+            is_folder: layer.layer_type == Some(LRCOM_FOLDER_TYPE),
+        }
+    }
+}
+
+impl LRComLayer {
+    /// lr.com stores a layer's color at the start of its name, as in `#ff0000name`
+    fn color(&self) -> Option<BoshTFColor> {
+        let hex = self.name.strip_prefix('#')?.get(..6)?;
+        let channel = |range| u8::from_str_radix(hex.get(range)?, 16).ok();
+
+        Some(BoshTFColor {
+            r: channel(0..2)?,
+            g: channel(2..4)?,
+            b: channel(4..6)?,
+        })
+    }
+}
+
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct LRComVec2 {
     x: f64,
@@ -65,6 +124,10 @@ pub struct LRComLine {
     left_extended: bool,
     #[serde(rename = "rightExtended")]
     right_extended: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    width: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    layer: Option<u64>,
 }
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
@@ -82,6 +145,8 @@ impl From<LRComLineArray> for LRComLine {
             right_extended: why.6 & 0b10 > 0,
             left_extended: why.6 & 0b1 > 0,
             flipped: why.7,
+            width: None,
+            layer: None,
         }
     }
 }
@@ -162,9 +227,10 @@ impl From<&LRComLineType> for BoshTFLineType {
 impl TryFrom<&BoshTFLine> for LRComLine {
     type Error = Error;
 
-    fn try_from(line: &BoshTFLine) -> Result<LRComLine> {
+    fn try_from(bosh_line: &BoshTFLine) -> Result<LRComLine> {
+        let line = &bosh_line.line;
         Ok(LRComLine {
-            id: 0,
+            id: bosh_line.id.context("cannot convert line without an id")?,
             line_type: (&line.line_type)
                 .try_into()
                 .context("cannot convert line")?,
@@ -175,20 +241,28 @@ impl TryFrom<&BoshTFLine> for LRComLine {
             flipped: line.flipped,
             left_extended: line.ends.0.extended,
             right_extended: line.ends.1.extended,
+            width: bosh_line.width,
+            layer: bosh_line.layer,
         })
     }
 }
 
 impl From<&LRComLine> for BoshTFLine {
     fn from(line: &LRComLine) -> BoshTFLine {
-        Line::builder()
-            .point(line.x1, line.y1)
-            .extended(line.left_extended)
-            .point(line.x2, line.y2)
-            .extended(line.right_extended)
-            .flipped(line.flipped)
-            .line_type((&line.line_type).into())
-            .build()
+        BoshTFLine {
+            id: Some(line.id),
+            width: line.width,
+            color: None,
+            layer: line.layer,
+            line: Line::builder()
+                .point(line.x1, line.y1)
+                .extended(line.left_extended)
+                .point(line.x2, line.y2)
+                .extended(line.right_extended)
+                .flipped(line.flipped)
+                .line_type((&line.line_type).into())
+                .build(),
+        }
     }
 }
 
@@ -200,10 +274,15 @@ impl TryFrom<&BoshTFEntity> for LRComEntity {
             BoshTFEntity::Custom(_) => {
                 Err(anyhow!("must be a boshsled to serialize to track.json"))
             }
-            BoshTFEntity::BoshSled { position, velocity } => Ok(LRComEntity {
+            BoshTFEntity::BoshSled {
+                position,
+                velocity,
+                remountable,
+                ..
+            } => Ok(LRComEntity {
                 start_position: position.into(),
                 start_velocity: velocity.into(),
-                remountable: 0,
+                remountable: *remountable as u8,
             }),
         }
     }
@@ -214,6 +293,8 @@ impl From<&LRComEntity> for BoshTFEntity {
         BoshTFEntity::BoshSled {
             velocity: (&entity.start_velocity).into(),
             position: (&entity.start_position).into(),
+            remountable: entity.remountable != 0,
+            frictionless: false,
         }
     }
 }
@@ -221,83 +302,368 @@ impl From<&LRComEntity> for BoshTFEntity {
 impl From<&LRComTrack> for BoshTFTrack {
     fn from(track: &LRComTrack) -> BoshTFTrack {
         let entities = if let Some(riders) = &track.riders {
-            riders
-                .iter()
-                .map(|ent| BoshTFEntity::BoshSled {
-                    velocity: (&ent.start_velocity).into(),
-                    position: (&ent.start_position).into(),
-                })
-                .collect()
+            riders.iter().map(BoshTFEntity::from).collect()
         } else {
             vec![BoshTFEntity::BoshSled {
                 position: (&track.start_position).into(),
                 velocity: Vector2D(0.4, 0.0),
+                remountable: false,
+                frictionless: false,
             }]
         };
 
-        let lines = if let Some(lines) = &track.lines {
+        let mut lines: Vec<BoshTFLine> = if let Some(lines) = &track.lines {
             lines.iter().map(|l| l.into()).collect()
         } else if let Some(lines) = &track.lines_array {
             lines
                 .iter()
-                .map(|l| {
-                    eprintln!("{:?}", l);
-                    let obj = &LRComLine::from(*l);
-                    eprintln!("{:?}", obj);
-                    let bosh = BoshTFLine::from(obj);
-                    eprintln!("{:?}", obj);
-                    bosh
-                })
+                .map(|l| BoshTFLine::from(&LRComLine::from(*l)))
                 .collect()
         } else {
             vec![]
         };
 
+        let layer_colors: HashMap<u64, BoshTFColor> = track
+            .layers
+            .iter()
+            .flatten()
+            .filter_map(|layer| Some((layer.id, layer.color()?)))
+            .collect();
+        for line in &mut lines {
+            line.color = line
+                .layer
+                .and_then(|layer| layer_colors.get(&layer).copied());
+        }
+
+        let meta = BoshTFMeta {
+            title: track.label.clone(),
+            author: track.creator.clone(),
+            description: track.description.clone(),
+            song: track.audio.as_ref().map(|name| BoshTFSong {
+                name: name.clone(),
+                offset_seconds: 0.0,
+            }),
+            duration: track.duration,
+            ..Default::default()
+        };
+
         BoshTFTrack {
-            meta: Default::default(),
+            meta,
             entities,
             lines,
+            triggers: vec![],
+            layers: track
+                .layers
+                .iter()
+                .flatten()
+                .map(BoshTFLayer::from)
+                .collect(),
         }
     }
 }
 
-impl TryFrom<&BoshTFTrack> for LRComTrack {
-    type Error = Error;
-
-    fn try_from(track: &BoshTFTrack) -> Result<LRComTrack> {
-        let first_entity = track
-            .entities
-            .get(0)
-            .context("error converting boshtf into lr.com: no entites")?;
-        let first_entity: LRComEntity = first_entity
-            .try_into()
-            .context("error converting boshtf into lr.com: bad entity")?;
+impl LRComTrack {
+    /// Converts a BoshTF track to lr.com, dropping or altering anything that
+    /// lr.com cannot represent. Each loss of information is reported as a warning.
+    pub fn from_boshtf_lossy(track: &BoshTFTrack) -> Result<(LRComTrack, Vec<ConversionWarning>)> {
+        let mut warnings = vec![];
 
         let mut riders: Vec<LRComEntity> = Vec::with_capacity(track.entities.len());
-        for e in &track.entities {
-            riders.push(
-                e.try_into()
-                    .context("error converting boshtf into lr.com: bad entity")?,
-            );
+        for (entity_index, e) in track.entities.iter().enumerate() {
+            match LRComEntity::try_from(e) {
+                Ok(rider) => {
+                    if let BoshTFEntity::BoshSled {
+                        frictionless: true, ..
+                    } = e
+                    {
+                        warnings.push(ConversionWarning::EntityAltered {
+                            entity_index,
+                            reason: "lr.com does not support frictionless riders".to_string(),
+                        });
+                    }
+                    riders.push(rider)
+                }
+                Err(err) => warnings.push(ConversionWarning::EntityDropped {
+                    entity_index,
+                    reason: format!("{:#}", err),
+                }),
+            }
         }
 
+        let mut track = track.clone();
+        track.assign_line_ids();
+
+        let layers: Vec<LRComLayer> = track
+            .layers
+            .iter()
+            .map(|layer| LRComLayer {
+                id: layer.id,
+                name: layer.name.clone(),
+                visible: layer.visible,
+                editable: layer.editable,
+                folder_id: layer.folder,
+                layer_type: layer.is_folder.then(|| LRCOM_FOLDER_TYPE),
+                size: layer.is_folder.then(|| {
+                    track
+                        .layers
+                        .iter()
+                        .filter(|l| l.folder == Some(layer.id))
+                        .count() as u64
+                }),
+            })
+            .collect();
+        let layer_colors: HashMap<u64, BoshTFColor> = layers
+            .iter()
+            .filter_map(|layer| Some((layer.id, layer.color()?)))
+            .collect();
+
         let mut lines: Vec<LRComLine> = Vec::with_capacity(track.lines.len());
-        for l in &track.lines {
-            lines.push(l.try_into().context("error converting line")?);
+        for (line_index, l) in track.lines.iter().enumerate() {
+            let line = match LRComLine::try_from(l) {
+                Ok(line) => line,
+                Err(err) => {
+                    warnings.push(ConversionWarning::LineAltered {
+                        line_index,
+                        reason: format!("{:#}, wrote an acceleration amount of 1 instead", err),
+                    });
+
+                    let mut fallback = l.clone();
+                    fallback.line.line_type = BoshTFLineType::Accelerate { amount: 1 };
+                    LRComLine::try_from(&fallback).context("error converting line")?
+                }
+            };
+            if l.color.is_some() && l.color != l.layer.and_then(|id| layer_colors.get(&id).copied())
+            {
+                warnings.push(ConversionWarning::LineAltered {
+                    line_index,
+                    reason: "lr.com only supports colors on layers".to_string(),
+                });
+            }
+            lines.push(line);
         }
 
-        Ok(LRComTrack {
-            next_line_id: lines.len() as u64,
-            label: None,
-            creator: None,
-            description: None,
-            duration: None,
+        for trigger_index in 0..track.triggers.len() {
+            warnings.push(ConversionWarning::TriggerDropped {
+                trigger_index,
+                reason: "lr.com does not support line triggers".to_string(),
+            });
+        }
+
+        if let Some(song) = &track.meta.song {
+            if song.offset_seconds != 0.0 {
+                warnings.push(ConversionWarning::MetaDropped {
+                    field: "song offset".to_string(),
+                    reason: "lr.com does not support song offsets".to_string(),
+                });
+            }
+        }
+        let unsupported_meta = [
+            ("start zoom", track.meta.start_zoom.is_some()),
+            ("gravity scale", track.meta.gravity_scale.is_some()),
+            ("gravity well size", track.meta.gravity_well_size.is_some()),
+            ("background color", track.meta.background_color.is_some()),
+            ("line color", track.meta.line_color.is_some()),
+        ];
+        for (field, _) in unsupported_meta.iter().filter(|(_, present)| *present) {
+            warnings.push(ConversionWarning::MetaDropped {
+                field: field.to_string(),
+                reason: "lr.com does not support this field".to_string(),
+            });
+        }
+        for key in track.meta.lra_entries.keys() {
+            warnings.push(ConversionWarning::MetaDropped {
+                field: key.clone(),
+                reason: "lr.com does not support LRA metadata".to_string(),
+            });
+        }
+
+        let start_position = riders
+            .first()
+            .map(|rider| rider.start_position)
+            .unwrap_or(LRComVec2 { x: 0.0, y: 0.0 });
+
+        let track = LRComTrack {
+            next_line_id: track.next_line_id(),
+            label: track.meta.title.clone(),
+            creator: track.meta.author.clone(),
+            description: track.meta.description.clone(),
+            duration: track.meta.duration,
             version: "6.2".to_string(),
-            audio: None,
-            start_position: first_entity.start_position,
+            audio: track.meta.song.as_ref().map(|song| song.name.clone()),
+            start_position,
             riders: Some(riders),
+            layers: (!layers.is_empty()).then(|| layers),
             lines: Some(lines),
             lines_array: None,
-        })
+        };
+
+        Ok((track, warnings))
+    }
+}
+
+impl TryFrom<&BoshTFTrack> for LRComTrack {
+    type Error = Error;
+
+    fn try_from(track: &BoshTFTrack) -> Result<LRComTrack> {
+        if track.entities.is_empty() {
+            return Err(anyhow!("error converting boshtf into lr.com: no entites"));
+        }
+
+        let (lrcom, warnings) = LRComTrack::from_boshtf_lossy(track)?;
+        if let Some(warning) = warnings.first() {
+            return Err(anyhow!("error converting boshtf into lr.com: {warning}"));
+        }
+
+        Ok(lrcom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layer(id: u64, name: &str, folder: Option<u64>, is_folder: bool) -> BoshTFLayer {
+        BoshTFLayer {
+            id,
+            name: name.to_string(),
+            visible: true,
+            editable: id != 3,
+            folder,
+            is_folder,
+        }
+    }
+
+    fn line(id: u64, layer: Option<u64>, color: Option<BoshTFColor>) -> BoshTFLine {
+        BoshTFLine {
+            id: Some(id),
+            width: None,
+            color,
+            layer,
+            line: Line::builder()
+                .point(id as f64 * 10.0, 0.0)
+                .point(id as f64 * 10.0 + 5.0, 5.0)
+                .build(),
+        }
+    }
+
+    fn json<T: Serialize>(value: &T) -> serde_json::Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    #[test]
+    fn layers_round_trip() {
+        let orange = BoshTFColor {
+            r: 0xff,
+            g: 0x80,
+            b: 0x00,
+        };
+        let track = BoshTFTrack {
+            meta: Default::default(),
+            entities: vec![BoshTFEntity::BoshSled {
+                position: Vector2D(0.0, 0.0),
+                velocity: Vector2D(0.4, 0.0),
+                remountable: false,
+                frictionless: false,
+            }],
+            lines: vec![
+                line(0, None, None),
+                line(1, Some(2), Some(orange)),
+                line(2, Some(3), None),
+            ],
+            triggers: vec![],
+            layers: vec![
+                layer(1, "folder", None, true),
+                layer(2, "#ff8000orange", Some(1), false),
+                layer(3, "locked", None, false),
+            ],
+        };
+
+        let (lrcom, warnings) = LRComTrack::from_boshtf_lossy(&track).unwrap();
+        assert!(warnings.is_empty(), "unexpected warnings: {:?}", warnings);
+
+        let text = serde_json::to_string(&lrcom).unwrap();
+        let read_back = BoshTFTrack::from(&serde_json::from_str::<LRComTrack>(&text).unwrap());
+
+        assert_eq!(json(&track.layers), json(&read_back.layers));
+        assert_eq!(json(&track.lines), json(&read_back.lines));
+    }
+
+    #[test]
+    fn folders_count_their_layers() {
+        let track = BoshTFTrack {
+            layers: vec![
+                layer(1, "folder", None, true),
+                layer(2, "a", Some(1), false),
+                layer(3, "b", Some(1), false),
+            ],
+            ..Default::default()
+        };
+
+        let (lrcom, _) = LRComTrack::from_boshtf_lossy(&track).unwrap();
+        let layers = lrcom.layers.unwrap();
+
+        assert_eq!(layers[0].layer_type, Some(LRCOM_FOLDER_TYPE));
+        assert_eq!(layers[0].size, Some(2));
+        assert_eq!(layers[1].layer_type, None);
+        assert_eq!(layers[1].size, None);
+    }
+
+    #[test]
+    fn colors_are_read_from_layer_names() {
+        let color = |name: &str| {
+            LRComLayer {
+                id: 0,
+                name: name.to_string(),
+                visible: true,
+                editable: true,
+                folder_id: None,
+                layer_type: None,
+                size: None,
+            }
+            .color()
+        };
+
+        assert_eq!(
+            color("#00ff7fgrass"),
+            Some(BoshTFColor {
+                r: 0x00,
+                g: 0xff,
+                b: 0x7f,
+            })
+        );
+        assert_eq!(
+            color("#ABCDEF"),
+            Some(BoshTFColor {
+                r: 0xab,
+                g: 0xcd,
+                b: 0xef,
+            })
+        );
+        assert_eq!(color("grass"), None);
+        assert_eq!(color("#00ff"), None);
+        assert_eq!(color("#00gg7fgrass"), None);
+        assert_eq!(color("00ff7fgrass"), None);
+    }
+
+    #[test]
+    fn line_colors_that_differ_from_their_layer_are_reported() {
+        let red = BoshTFColor {
+            r: 0xff,
+            g: 0,
+            b: 0,
+        };
+        let track = BoshTFTrack {
+            lines: vec![line(0, None, Some(red)), line(1, Some(1), Some(red))],
+            layers: vec![layer(1, "#ff0000red", None, false)],
+            ..Default::default()
+        };
+
+        let (_, warnings) = LRComTrack::from_boshtf_lossy(&track).unwrap();
+
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(
+            warnings[0],
+            ConversionWarning::LineAltered { line_index: 0, .. }
+        ));
     }
 }
@@ -1,3 +1,88 @@
+use std::fmt::{Display, Formatter};
+
+use serde::{Deserialize, Serialize};
+
 pub mod boshtf;
 pub mod lrcom;
 pub mod trk;
+
+/// The on-disk formats that bosh can read and write.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TrackFormat {
+    #[serde(rename = "boshtf")]
+    BoshTF,
+    #[serde(rename = "track.json")]
+    LRCom,
+    #[serde(rename = "trk")]
+    Trk,
+}
+
+impl TrackFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TrackFormat::BoshTF => "boshtf",
+            TrackFormat::LRCom => "track.json",
+            TrackFormat::Trk => "trk",
+        }
+    }
+
+    /// Finds the format whose extension ends the given (possibly multi-part) extension.
+    pub fn from_extension(extension: &str) -> Option<TrackFormat> {
+        [TrackFormat::BoshTF, TrackFormat::LRCom, TrackFormat::Trk]
+            .into_iter()
+            .find(|format| {
+                extension == format.extension()
+                    || extension.ends_with(&format!(".{}", format.extension()))
+            })
+    }
+}
+
+/// Information that was lost or altered while converting a track into a
+/// format that cannot represent it.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+pub enum ConversionWarning {
+    /// An entity could not be represented and was left out.
+    #[serde(rename_all = "camelCase")]
+    EntityDropped { entity_index: usize, reason: String },
+    /// An entity was written, but with different properties.
+    #[serde(rename_all = "camelCase")]
+    EntityAltered { entity_index: usize, reason: String },
+    /// A line was written, but with different properties.
+    #[serde(rename_all = "camelCase")]
+    LineAltered { line_index: usize, reason: String },
+    /// A metadata field could not be represented and was left out.
+    #[serde(rename_all = "camelCase")]
+    MetaDropped { field: String, reason: String },
+    /// A trigger could not be represented and was left out.
+    #[serde(rename_all = "camelCase")]
+    TriggerDropped {
+        trigger_index: usize,
+        reason: String,
+    },
+}
+
+impl Display for ConversionWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConversionWarning::EntityDropped {
+                entity_index,
+                reason,
+            } => write!(f, "entity {entity_index} was dropped: {reason}"),
+            ConversionWarning::EntityAltered {
+                entity_index,
+                reason,
+            } => write!(f, "entity {entity_index} was altered: {reason}"),
+            ConversionWarning::LineAltered { line_index, reason } => {
+                write!(f, "line {line_index} was altered: {reason}")
+            }
+            ConversionWarning::MetaDropped { field, reason } => {
+                write!(f, "metadata {field} was dropped: {reason}")
+            }
+            ConversionWarning::TriggerDropped {
+                trigger_index,
+                reason,
+            } => write!(f, "trigger {trigger_index} was dropped: {reason}"),
+        }
+    }
+}
@@ -1,16 +1,20 @@
-//! Module for deserializing LRA tracks, aka .trk files.
+//! Module for serializing and deserializing LRA tracks, aka .trk files.
 
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::{Display, Formatter};
 use std::io;
-use std::io::Read;
+use std::io::{Read, Write};
+use std::str::FromStr;
 
 use anyhow::{anyhow, Context, Error, Result};
 use bosh_rs::{Line, LineType, Vector2D};
-use read_from::{LittleEndian, ReadFrom};
+use read_from::{LittleEndian, ReadFrom, WriteTo};
 
-use crate::serialization::boshtf::{BoshTFLine, BoshTFLineType};
-use crate::{BoshTFEntity, BoshTFTrack};
+use crate::serialization::boshtf::{
+    BoshTFColor, BoshTFEntity, BoshTFLine, BoshTFLineType, BoshTFMeta, BoshTFSong, BoshTFTrack,
+    BoshTFTrigger,
+};
+use crate::serialization::ConversionWarning;
 
 pub enum TrkFeature {
     RedMultiplier,
@@ -77,16 +81,90 @@ pub enum TrkLineType {
 #[derive(Default, Copy, Clone)]
 pub struct TrkLineFlags(pub u8);
 
+/// The song played alongside a track, stored as `name\r\noffset`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrkSong {
+    pub name: String,
+    pub offset_seconds: f64,
+}
+
 #[derive(Clone)]
 pub struct TrkHeader {
     pub version: u8,
     pub features: HashSet<String>,
 
-    pub song: Option<String>,
+    pub song: Option<TrkSong>,
     pub start_position: Vector2D,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct TrkColor {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// The metadata entries that bosh understands.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrkMetaSettings {
+    pub start_zoom: Option<f32>,
+    pub x_gravity: Option<f32>,
+    pub y_gravity: Option<f32>,
+    pub gravity_well_size: Option<f64>,
+    pub background_color: Option<TrkColor>,
+    pub line_color: Option<TrkColor>,
+    /// every rider in the track, for tracks that the header cannot fully describe
+    pub riders: Option<Vec<TrkRider>>,
+}
+
+/// A rider stored in the `RIDERS` metadata entry as `x,y,vx,vy,remountable`.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TrkRider {
+    pub position: Vector2D,
+    pub velocity: Vector2D,
+    pub remountable: bool,
+}
+
+impl FromStr for TrkRider {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<TrkRider> {
+        let values: Vec<&str> = s.split(',').map(str::trim).collect();
+        if values.len() != 5 {
+            return Err(anyhow!("rider should have 5 values: {}", s));
+        }
+        let number = |index: usize| -> Result<f64> {
+            values[index]
+                .parse()
+                .with_context(|| format!("not a number in rider: {}", values[index]))
+        };
+
+        Ok(TrkRider {
+            position: Vector2D(number(0)?, number(1)?),
+            velocity: Vector2D(number(2)?, number(3)?),
+            remountable: values[4] == "1",
+        })
+    }
+}
+
+impl Display for TrkRider {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{},{},{},{},{}",
+            self.position.0,
+            self.position.1,
+            self.velocity.0,
+            self.velocity.1,
+            self.remountable as u8
+        )
+    }
+}
+
 pub struct TrkMeta {
+    pub settings: TrkMetaSettings,
+    /// entries that are not understood by bosh, kept verbatim. This includes
+    /// `TRIGGERS`, LRA's timed zoom and color triggers, which bosh cannot play.
     pub entries: HashMap<String, String>,
 }
 
@@ -96,12 +174,20 @@ pub struct TrkTrack {
     pub meta: Option<TrkMeta>,
 }
 
+#[derive(Copy, Clone)]
+pub struct TrkZoomTrigger {
+    pub target: f32,
+    pub frames: i16,
+}
+
 #[derive(Default, Clone)]
 pub struct TrkLine {
     pub flags: TrkLineFlags,
     pub multiplier: u8,
+    pub trigger: Option<TrkZoomTrigger>,
     pub id: i32,
-    pub line_width: u8,
+    /// scenery line width multiplied by 10, only present with SCENERYWIDTH
+    pub line_width: Option<u8>,
     pub start: Vector2D,
     pub end: Vector2D,
 }
@@ -110,8 +196,8 @@ impl TrkLineFlags {
     pub fn new(flipped: bool, extensions: (bool, bool), line_type: TrkLineType) -> TrkLineFlags {
         let mut byte = 0u8;
         byte |= (flipped as u8) << 7;
-        byte |= (extensions.0 as u8) << 6;
-        byte |= (extensions.1 as u8) << 5;
+        byte |= (extensions.0 as u8) << 5;
+        byte |= (extensions.1 as u8) << 6;
         byte |= line_type as u8 & 0b00011111;
 
         TrkLineFlags(byte)
@@ -129,6 +215,43 @@ impl TrkLineFlags {
 
 // ======= DESERIALIZATION ========
 
+/// Reads a length encoded 7 bits at a time, the way .NET's `BinaryReader` does.
+fn read_7bit_length<R: Read>(mut input: R) -> Result<usize> {
+    let mut length = 0usize;
+    for shift in (0..35).step_by(7) {
+        let byte = u8::read_from(&mut input).context("error while reading 7-bit length")?;
+        length |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(length);
+        }
+    }
+
+    Err(anyhow!("7-bit length is longer than 5 bytes"))
+}
+
+impl ReadFrom for TrkSong {
+    type Error = Error;
+
+    fn read_from<R: Read>(mut input: R) -> Result<TrkSong> {
+        let song_length =
+            read_7bit_length(&mut input).context("error while reading song length")?;
+        let mut song = vec![0; song_length];
+        input
+            .read_exact(song.as_mut_slice())
+            .with_context(|| format!("error while reading song of length {song_length}"))?;
+
+        // a missing or unreadable offset should not keep the rest of the track from loading
+        let song = String::from_utf8_lossy(song.as_slice());
+        let (name, offset) = song.split_once("\r\n").unwrap_or((song.as_ref(), ""));
+        let offset_seconds = offset.trim().parse().unwrap_or(0.0);
+
+        Ok(TrkSong {
+            name: name.to_owned(),
+            offset_seconds,
+        })
+    }
+}
+
 impl ReadFrom for TrkLineFlags {
     type Error = Error;
 
@@ -176,20 +299,7 @@ impl TrkHeader {
             .collect();
 
         let song = if features.contains(&TrkFeature::SongInfo.to_string()) {
-            let song_length =
-                u8::read_from(&mut input).context("error while reading header, song length")?;
-            let mut song = vec![0; song_length as usize];
-
-            input.read_exact(song.as_mut_slice()).with_context(|| {
-                format!("error while reading header, in song of length {song_length}")
-            })?;
-
-            let song = String::from_utf8_lossy(features_string.as_slice())
-                .split(';')
-                .map(|s| s.to_owned())
-                .collect();
-
-            Some(song)
+            Some(TrkSong::read_from(&mut input).context("error while reading header, song")?)
         } else {
             None
         };
@@ -220,16 +330,20 @@ impl TrkLine {
             1
         };
 
+        let mut trigger = None;
+
         // red/blue line specific logic
         let id = if matches!(flags.line_type(), TrkLineType::Red | TrkLineType::Blue) {
-            // ignore line triggers
             if features.contains(&TrkFeature::IgnorableTrigger.to_string()) {
                 let zoom = u8::read_from(&mut input).context("error while reading zoom trigger")?;
                 if zoom != 0 {
-                    LittleEndian::<f32>::read_from(&mut input)
-                        .context("error while reading zoom target")?;
-                    LittleEndian::<i16>::read_from(&mut input)
-                        .context("error while reading zoom length")?;
+                    let target = LittleEndian::<f32>::read_from(&mut input)
+                        .context("error while reading zoom target")?
+                        .0;
+                    let frames = LittleEndian::<i16>::read_from(&mut input)
+                        .context("error while reading zoom length")?
+                        .0;
+                    trigger = Some(TrkZoomTrigger { target, frames });
                 }
             }
 
@@ -253,9 +367,9 @@ impl TrkLine {
         let line_width = if features.contains(&TrkFeature::SceneryWidth.to_string())
             && matches!(flags.line_type(), TrkLineType::Scenery)
         {
-            u8::read_from(&mut input).context("error while reading scenery width")?
+            Some(u8::read_from(&mut input).context("error while reading scenery width")?)
         } else {
-            1
+            None
         };
 
         let start = Vector2D(
@@ -278,6 +392,7 @@ impl TrkLine {
         Ok(TrkLine {
             flags,
             multiplier,
+            trigger,
             id,
             line_width,
             start,
@@ -320,8 +435,69 @@ impl TrkMeta {
             map.insert(key.to_owned(), value.to_owned());
         }
 
-        Ok(Some(TrkMeta { entries: map }))
+        let settings = TrkMetaSettings::take_from(&mut map);
+
+        Ok(Some(TrkMeta {
+            settings,
+            entries: map,
+        }))
+    }
+}
+
+/// Removes and parses `key`, leaving it in place if it cannot be parsed.
+fn take_parsed<T: FromStr>(entries: &mut HashMap<String, String>, key: &str) -> Option<T> {
+    let value = entries.get(key)?.trim().parse().ok()?;
+    entries.remove(key);
+
+    Some(value)
+}
+
+fn take_color(entries: &mut HashMap<String, String>, prefix: &str) -> Option<TrkColor> {
+    let keys = ["R", "G", "B"].map(|channel| format!("{prefix}{channel}"));
+    let mut channels = [0u8; 3];
+    for (channel, key) in channels.iter_mut().zip(&keys) {
+        *channel = entries.get(key)?.trim().parse().ok()?;
+    }
+    for key in &keys {
+        entries.remove(key);
+    }
+
+    Some(TrkColor {
+        r: channels[0],
+        g: channels[1],
+        b: channels[2],
+    })
+}
+
+impl TrkMetaSettings {
+    /// Takes every understood entry out of `entries`, leaving the rest behind.
+    fn take_from(entries: &mut HashMap<String, String>) -> TrkMetaSettings {
+        TrkMetaSettings {
+            start_zoom: take_parsed(entries, "STARTZOOM"),
+            x_gravity: take_parsed(entries, "XGRAVITY"),
+            y_gravity: take_parsed(entries, "YGRAVITY"),
+            gravity_well_size: take_parsed(entries, "GRAVITYWELLSIZE"),
+            background_color: take_color(entries, "BGCOLOR"),
+            line_color: take_color(entries, "LINECOLOR"),
+            riders: take_riders(entries),
+        }
+    }
+}
+
+fn take_riders(entries: &mut HashMap<String, String>) -> Option<Vec<TrkRider>> {
+    let riders = entries
+        .get("RIDERS")?
+        .split(';')
+        .filter(|rider| !rider.is_empty())
+        .map(|rider| rider.parse().ok())
+        .collect::<Option<Vec<TrkRider>>>()?;
+    // a track without riders falls back to the one in the header instead
+    if riders.is_empty() {
+        return None;
     }
+    entries.remove("RIDERS");
+
+    Some(riders)
 }
 
 impl ReadFrom for TrkTrack {
@@ -350,8 +526,312 @@ impl ReadFrom for TrkTrack {
     }
 }
 
+// ======= SERIALIZATION ========
+
+/// Writes a length encoded 7 bits at a time, the way .NET's `BinaryWriter` does.
+fn write_7bit_length<W: Write>(mut output: W, mut length: usize) -> Result<usize> {
+    let mut written = 0;
+    loop {
+        let mut byte = (length & 0x7F) as u8;
+        length >>= 7;
+        if length != 0 {
+            byte |= 0x80;
+        }
+        written += byte
+            .write_to(&mut output)
+            .context("error while writing 7-bit length")?;
+        if length == 0 {
+            return Ok(written);
+        }
+    }
+}
+
+impl WriteTo for TrkSong {
+    type Error = Error;
+
+    fn write_to<W: Write>(&self, mut output: W) -> Result<usize> {
+        let song = format!("{}\r\n{}", self.name, self.offset_seconds);
+
+        let mut written = write_7bit_length(&mut output, song.len())
+            .context("error while writing song length")?;
+        output
+            .write_all(song.as_bytes())
+            .context("error while writing song")?;
+        written += song.len();
+
+        Ok(written)
+    }
+}
+
+impl WriteTo for TrkLineFlags {
+    type Error = Error;
+
+    fn write_to<W: Write>(&self, mut output: W) -> Result<usize> {
+        self.0
+            .write_to(&mut output)
+            .context("error writing line flags")
+    }
+}
+
+fn write_vector<W: Write>(mut output: W, vector: Vector2D) -> Result<usize> {
+    let mut written = 0;
+    written += LittleEndian(vector.0)
+        .write_to(&mut output)
+        .context("error while writing x")?;
+    written += LittleEndian(vector.1)
+        .write_to(&mut output)
+        .context("error while writing y")?;
+
+    Ok(written)
+}
+
+impl TrkHeader {
+    fn write_to<W: Write>(&self, mut output: W) -> Result<usize> {
+        let mut written = 0;
+
+        output
+            .write_all(&[b'T', b'R', b'K', 0xF2])
+            .context("error while writing magic value in header")?;
+        written += 4;
+
+        written += self
+            .version
+            .write_to(&mut output)
+            .context("error while writing version in header")?;
+
+        // sorted so that saving the same track always gives the same bytes
+        let mut features: Vec<&String> = self.features.iter().filter(|f| !f.is_empty()).collect();
+        features.sort();
+
+        // LRA terminates every feature with a semicolon, including the last one
+        let mut features_string = String::new();
+        for feature in features {
+            features_string.push_str(feature);
+            features_string.push(';');
+        }
+        let features_length = u16::try_from(features_string.len())
+            .context("features-string in header is too long")?;
+
+        written += LittleEndian(features_length)
+            .write_to(&mut output)
+            .context("error while writing length of features-string in header")?;
+        output
+            .write_all(features_string.as_bytes())
+            .context("error while writing features-string in header")?;
+        written += features_string.len();
+
+        if self.features.contains(&TrkFeature::SongInfo.to_string()) {
+            let song = self
+                .song
+                .as_ref()
+                .context("header has SONGINFO feature, but no song")?;
+            written += song
+                .write_to(&mut output)
+                .context("error while writing header, song")?;
+        }
+
+        written += write_vector(&mut output, self.start_position)
+            .context("error while writing header, start position")?;
+
+        Ok(written)
+    }
+}
+
+// cannot actually implement WriteTo trait because TrkLine requires features to be passed in
+impl TrkLine {
+    fn write_to<W: Write>(&self, mut output: W, features: &HashSet<String>) -> Result<usize> {
+        let mut written = 0;
+
+        written += self
+            .flags
+            .write_to(&mut output)
+            .context("error while writing line flags")?;
+
+        if features.contains(&TrkFeature::RedMultiplier.to_string())
+            && self.flags.line_type() == TrkLineType::Red
+        {
+            written += self
+                .multiplier
+                .write_to(&mut output)
+                .context("error while writing red line multiplier")?;
+        }
+
+        // red/blue line specific logic
+        if matches!(self.flags.line_type(), TrkLineType::Red | TrkLineType::Blue) {
+            if features.contains(&TrkFeature::IgnorableTrigger.to_string()) {
+                written += (self.trigger.is_some() as u8)
+                    .write_to(&mut output)
+                    .context("error while writing zoom trigger")?;
+                if let Some(trigger) = &self.trigger {
+                    written += LittleEndian(trigger.target)
+                        .write_to(&mut output)
+                        .context("error while writing zoom target")?;
+                    written += LittleEndian(trigger.frames)
+                        .write_to(&mut output)
+                        .context("error while writing zoom length")?;
+                }
+            }
+
+            written += LittleEndian(self.id)
+                .write_to(&mut output)
+                .context("error while writing line id")?;
+
+            if self.flags.extensions().0 || self.flags.extensions().1 {
+                // LRA writes -1 for both line ext data values
+                written += LittleEndian(-1i32)
+                    .write_to(&mut output)
+                    .context("error while writing line ext data 1")?;
+                written += LittleEndian(-1i32)
+                    .write_to(&mut output)
+                    .context("error while writing line ext data 2")?;
+            }
+        }
+
+        if features.contains(&TrkFeature::SceneryWidth.to_string())
+            && matches!(self.flags.line_type(), TrkLineType::Scenery)
+        {
+            written += self
+                .line_width
+                .unwrap_or(10)
+                .write_to(&mut output)
+                .context("error while writing scenery width")?;
+        }
+
+        written +=
+            write_vector(&mut output, self.start).context("error while writing line start")?;
+        written += write_vector(&mut output, self.end).context("error while writing line end")?;
+
+        Ok(written)
+    }
+}
+
+impl TrkMeta {
+    fn write_to<W: Write>(&self, mut output: W) -> Result<usize> {
+        let mut written = 0;
+
+        output
+            .write_all(&[b'M', b'E', b'T', b'A'])
+            .context("error while writing magic value in metadata")?;
+        written += 4;
+
+        let mut unknown: Vec<(String, String)> = self
+            .entries
+            .iter()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect();
+        unknown.sort();
+
+        let mut entries = self.settings.to_entries();
+        entries.extend(unknown);
+
+        let count = i16::try_from(entries.len()).context("too many metadata entries")?;
+        written += LittleEndian(count)
+            .write_to(&mut output)
+            .context("error while writing number of metadata entries")?;
+
+        for (key, value) in &entries {
+            let full_str = format!("{key}={value}");
+            let str_length = i16::try_from(full_str.len())
+                .with_context(|| format!("metadata entry is too long: {}", full_str))?;
+
+            written += LittleEndian(str_length)
+                .write_to(&mut output)
+                .context("error while writing length of a metadata entry")?;
+            output
+                .write_all(full_str.as_bytes())
+                .context("error while writing metadata entry")?;
+            written += full_str.len();
+        }
+
+        Ok(written)
+    }
+}
+
+impl TrkMetaSettings {
+    fn to_entries(&self) -> Vec<(String, String)> {
+        let mut entries = vec![];
+        let mut push = |key: &str, value: Option<String>| {
+            if let Some(value) = value {
+                entries.push((key.to_owned(), value));
+            }
+        };
+
+        push("STARTZOOM", self.start_zoom.map(|v| v.to_string()));
+        push("XGRAVITY", self.x_gravity.map(|v| v.to_string()));
+        push("YGRAVITY", self.y_gravity.map(|v| v.to_string()));
+        push(
+            "GRAVITYWELLSIZE",
+            self.gravity_well_size.map(|v| v.to_string()),
+        );
+        for (prefix, color) in [
+            ("BGCOLOR", self.background_color),
+            ("LINECOLOR", self.line_color),
+        ] {
+            if let Some(color) = color {
+                push(&format!("{prefix}R"), Some(color.r.to_string()));
+                push(&format!("{prefix}G"), Some(color.g.to_string()));
+                push(&format!("{prefix}B"), Some(color.b.to_string()));
+            }
+        }
+        push(
+            "RIDERS",
+            self.riders.as_ref().map(|riders| {
+                riders
+                    .iter()
+                    .map(TrkRider::to_string)
+                    .collect::<Vec<String>>()
+                    .join(";")
+            }),
+        );
+
+        entries
+    }
+}
+
+impl WriteTo for TrkTrack {
+    type Error = Error;
+
+    fn write_to<W: Write>(&self, mut output: W) -> Result<usize> {
+        let mut written = 0;
+
+        written += self
+            .header
+            .write_to(&mut output)
+            .context("error while writing header in track")?;
+
+        let line_count =
+            u32::try_from(self.lines.len()).context("too many lines to write in track")?;
+        written += LittleEndian(line_count)
+            .write_to(&mut output)
+            .context("error while writing line count in track")?;
+        for line in &self.lines {
+            written += line
+                .write_to(&mut output, &self.header.features)
+                .context("error while writing line in track")?;
+        }
+
+        if let Some(meta) = &self.meta {
+            written += meta
+                .write_to(&mut output)
+                .context("error while writing meta in track")?;
+        }
+
+        Ok(written)
+    }
+}
+
 // ======= TRK -> BOSHTF ========
 
+impl From<TrkColor> for BoshTFColor {
+    fn from(color: TrkColor) -> Self {
+        BoshTFColor {
+            r: color.r,
+            g: color.g,
+            b: color.b,
+        }
+    }
+}
+
 impl TrkLineType {
     fn as_boshtf(&self, multiplier: u8) -> BoshTFLineType {
         match self {
@@ -366,40 +846,128 @@ impl TrkLineType {
 
 impl From<&TrkLine> for BoshTFLine {
     fn from(line: &TrkLine) -> Self {
-        BoshTFLine::builder()
-            .extension_ratio(0.25)
-            .flipped(line.flags.flipped())
-            .line_type(line.flags.line_type().as_boshtf(line.multiplier))
-            .point_vec(line.start)
-            .extended(line.flags.extensions().0)
-            .point_vec(line.end)
-            .extended(line.flags.extensions().1)
-            .build()
+        // scenery lines do not have their ids stored in trk files
+        let id = if line.flags.line_type() == TrkLineType::Scenery {
+            None
+        } else {
+            u64::try_from(line.id).ok()
+        };
+
+        BoshTFLine {
+            id,
+            width: line.line_width.map(|width| width as f64 / 10.0),
+            color: None,
+            layer: None,
+            line: Line::builder()
+                .extension_ratio(0.25)
+                .flipped(line.flags.flipped())
+                .line_type(line.flags.line_type().as_boshtf(line.multiplier))
+                .point_vec(line.start)
+                .extended(line.flags.extensions().0)
+                .point_vec(line.end)
+                .extended(line.flags.extensions().1)
+                .build(),
+        }
     }
 }
 
 impl From<&TrkTrack> for BoshTFTrack {
     fn from(trk: &TrkTrack) -> Self {
-        let zero_start = trk.header.features.contains(TrkFeature::ZeroStart.into());
-        let rider = BoshTFEntity::BoshSled {
-            position: trk.header.start_position,
-            velocity: if zero_start {
-                Vector2D(0.0, 0.0)
+        let has_feature = |feature: TrkFeature| trk.header.features.contains(feature.into());
+        let settings = trk
+            .meta
+            .as_ref()
+            .map(|meta| meta.settings.clone())
+            .unwrap_or_default();
+
+        let frictionless = has_feature(TrkFeature::Frictionless);
+        let entities = match &settings.riders {
+            Some(riders) => riders
+                .iter()
+                .map(|rider| BoshTFEntity::BoshSled {
+                    position: rider.position,
+                    velocity: rider.velocity,
+                    remountable: rider.remountable,
+                    frictionless,
+                })
+                .collect(),
+            None => vec![BoshTFEntity::BoshSled {
+                position: trk.header.start_position,
+                velocity: if has_feature(TrkFeature::ZeroStart) {
+                    Vector2D(0.0, 0.0)
+                } else {
+                    Vector2D(0.4, 0.0)
+                },
+                remountable: has_feature(TrkFeature::Remount),
+                frictionless,
+            }],
+        };
+        let lra_entries: BTreeMap<String, String> = trk
+            .meta
+            .iter()
+            .flat_map(|meta| meta.entries.clone())
+            .collect();
+
+        let meta = BoshTFMeta {
+            song: trk.header.song.as_ref().map(|song| BoshTFSong {
+                name: song.name.clone(),
+                offset_seconds: song.offset_seconds,
+            }),
+            start_zoom: settings.start_zoom,
+            gravity_scale: if settings.x_gravity.is_some() || settings.y_gravity.is_some() {
+                Some(Vector2D(
+                    settings.x_gravity.unwrap_or(0.0) as f64,
+                    settings.y_gravity.unwrap_or(1.0) as f64,
+                ))
             } else {
-                Vector2D(0.4, 0.0)
+                None
             },
+            gravity_well_size: settings.gravity_well_size,
+            background_color: settings.background_color.map(BoshTFColor::from),
+            line_color: settings.line_color.map(BoshTFColor::from),
+            lra_entries,
+            ..Default::default()
         };
 
-        BoshTFTrack {
-            meta: Default::default(),
-            entities: vec![rider],
+        let mut track = BoshTFTrack {
+            meta,
+            entities,
             lines: trk.lines.iter().map(|l| l.into()).collect(),
-        }
+            triggers: vec![],
+            layers: vec![],
+        };
+        track.assign_line_ids();
+
+        track.triggers = trk
+            .lines
+            .iter()
+            .zip(&track.lines)
+            .filter_map(|(trk_line, line)| {
+                let trigger = trk_line.trigger?;
+                Some(BoshTFTrigger::Zoom {
+                    line: line.id?,
+                    target: trigger.target,
+                    frames: trigger.frames,
+                })
+            })
+            .collect();
+
+        track
     }
 }
 
 // =========== BOSHTF -> TRK ===============
 
+impl From<BoshTFColor> for TrkColor {
+    fn from(color: BoshTFColor) -> Self {
+        TrkColor {
+            r: color.r,
+            g: color.g,
+            b: color.b,
+        }
+    }
+}
+
 impl From<&LineType> for TrkLineType {
     fn from(line_type: &LineType) -> Self {
         match line_type {
@@ -419,35 +987,529 @@ impl From<&Line> for TrkLine {
                 (&line.line_type).into(),
             ),
             multiplier: if let LineType::Accelerate { amount } = line.line_type {
-                amount as u8
+                amount.min(u8::MAX as u64) as u8
             } else {
-                0
+                1
             },
+            trigger: None,
             id: 0,
-            line_width: 1,
+            line_width: None,
             start: line.ends.0.location,
             end: line.ends.1.location,
         }
     }
 }
 
-impl TryFrom<&BoshTFTrack> for TrkTrack {
-    type Error = Error;
+impl TrkTrack {
+    /// Converts a BoshTF track to trk, dropping or altering anything that
+    /// LRA cannot represent. Each loss of information is reported as a warning.
+    pub fn from_boshtf_lossy(track: &BoshTFTrack) -> Result<(TrkTrack, Vec<ConversionWarning>)> {
+        let mut warnings = vec![];
+        let mut features = HashSet::new();
+
+        let mut riders: Vec<(usize, bool, TrkRider)> = vec![];
+        for (entity_index, entity) in track.entities.iter().enumerate() {
+            match entity {
+                BoshTFEntity::BoshSled {
+                    position,
+                    velocity,
+                    remountable,
+                    frictionless,
+                } => riders.push((
+                    entity_index,
+                    *frictionless,
+                    TrkRider {
+                        position: *position,
+                        velocity: *velocity,
+                        remountable: *remountable,
+                    },
+                )),
+                BoshTFEntity::Custom(_) => warnings.push(ConversionWarning::EntityDropped {
+                    entity_index,
+                    reason: "must be a boshsled to serialize to trk".to_string(),
+                }),
+            }
+        }
 
-    fn try_from(track: &BoshTFTrack) -> Result<TrkTrack> {
-        if track.entities.len() > 1 {
-            return Err(anyhow!("trk format only supports 1 entity"));
+        // LRA applies FRICTIONLESS and REMOUNT to every rider in the track
+        if riders.iter().any(|(_, frictionless, _)| *frictionless) {
+            features.insert(TrkFeature::Frictionless.to_string());
+            for (entity_index, _, _) in riders.iter().filter(|(_, f, _)| !*f) {
+                warnings.push(ConversionWarning::EntityAltered {
+                    entity_index: *entity_index,
+                    reason:
+                        "trk format applies FRICTIONLESS to every rider, wrote it as frictionless"
+                            .to_string(),
+                });
+            }
+        }
+        if riders.iter().any(|(_, _, rider)| rider.remountable) {
+            features.insert(TrkFeature::Remount.to_string());
+            for (entity_index, _, _) in riders.iter().filter(|(_, _, r)| !r.remountable) {
+                warnings.push(ConversionWarning::EntityAltered {
+                    entity_index: *entity_index,
+                    reason: "trk format applies REMOUNT to every rider, wrote it as remountable"
+                        .to_string(),
+                });
+            }
         }
 
-        Ok(TrkTrack {
+        let first_rider = riders.first().map(|(_, _, rider)| rider);
+        let start_position = first_rider.map_or(Vector2D(0.0, 0.0), |rider| rider.position);
+        let start_velocity = first_rider.map_or(Vector2D(0.4, 0.0), |rider| rider.velocity);
+        if start_velocity == Vector2D(0.0, 0.0) {
+            features.insert(TrkFeature::ZeroStart.to_string());
+        }
+
+        // the header can only describe one rider with a starting velocity of (0.4, 0) or
+        // (0, 0), anything more is stored in the RIDERS metadata entry
+        let standard_velocity =
+            start_velocity == Vector2D(0.0, 0.0) || start_velocity == Vector2D(0.4, 0.0);
+        let header_is_enough = riders.len() <= 1 && standard_velocity;
+        let riders_entry = if header_is_enough {
+            None
+        } else {
+            // LRA itself never reads the RIDERS entry, so it only simulates the first
+            // rider, and with the header's starting velocity
+            if !standard_velocity {
+                if let Some((entity_index, _, _)) = riders.first() {
+                    warnings.push(ConversionWarning::EntityAltered {
+                        entity_index: *entity_index,
+                        reason: "LRA only starts riders with a velocity of (0.4, 0) or (0, 0), \
+                            the velocity is only kept in the RIDERS meta entry"
+                            .to_string(),
+                    });
+                }
+            }
+            for (entity_index, _, _) in riders.iter().skip(1) {
+                warnings.push(ConversionWarning::EntityAltered {
+                    entity_index: *entity_index,
+                    reason: "LRA only simulates the first rider, \
+                        this one is only kept in the RIDERS meta entry"
+                        .to_string(),
+                });
+            }
+
+            Some(riders.into_iter().map(|(_, _, rider)| rider).collect())
+        };
+
+        let mut track = track.clone();
+        track.assign_line_ids();
+
+        // trk ids are 32-bit, so larger ids need to be replaced
+        let mut next_trk_id = track
+            .lines
+            .iter()
+            .filter_map(|l| i32::try_from(l.id?).ok())
+            .max()
+            .map_or(0, |id| id + 1);
+
+        let mut lines: Vec<TrkLine> = Vec::with_capacity(track.lines.len());
+        let mut line_indices: HashMap<u64, usize> = HashMap::with_capacity(track.lines.len());
+        for (line_index, bosh_line) in track.lines.iter().enumerate() {
+            let BoshTFLine { id, line, .. } = bosh_line;
+            let mut trk_line = TrkLine::from(line);
+            if let LineType::Accelerate { amount } = line.line_type {
+                trk_line.multiplier = u8::try_from(amount).unwrap_or_else(|_| {
+                    warnings.push(ConversionWarning::LineAltered {
+                        line_index,
+                        reason: format!(
+                            "trk format only supports acceleration amounts up to {}, wrote {} instead of {amount}",
+                            u8::MAX,
+                            u8::MAX
+                        ),
+                    });
+                    u8::MAX
+                });
+                if trk_line.multiplier != 1 {
+                    features.insert(TrkFeature::RedMultiplier.to_string());
+                }
+            }
+            if let Some(width) = bosh_line.width {
+                if trk_line.flags.line_type() == TrkLineType::Scenery {
+                    let trk_width = (width * 10.0).round();
+                    if !(0.0..=u8::MAX as f64).contains(&trk_width) {
+                        warnings.push(ConversionWarning::LineAltered {
+                            line_index,
+                            reason: format!("trk format cannot store a line width of {width}"),
+                        });
+                    }
+                    trk_line.line_width = Some(trk_width.clamp(0.0, u8::MAX as f64) as u8);
+                    features.insert(TrkFeature::SceneryWidth.to_string());
+                } else {
+                    warnings.push(ConversionWarning::LineAltered {
+                        line_index,
+                        reason: "trk format only supports widths on scenery lines".to_string(),
+                    });
+                }
+            }
+            if bosh_line.color.is_some() {
+                warnings.push(ConversionWarning::LineAltered {
+                    line_index,
+                    reason: "trk format does not support line colors".to_string(),
+                });
+            }
+            if bosh_line.layer.is_some() {
+                warnings.push(ConversionWarning::LineAltered {
+                    line_index,
+                    reason: "trk format does not support layers".to_string(),
+                });
+            }
+            if let Some(id) = id {
+                line_indices.insert(*id, line_index);
+            }
+            trk_line.id = match id.and_then(|id| i32::try_from(id).ok()) {
+                Some(trk_id) => trk_id,
+                None => {
+                    let trk_id = next_trk_id;
+                    next_trk_id += 1;
+                    if trk_line.flags.line_type() != TrkLineType::Scenery {
+                        warnings.push(ConversionWarning::LineAltered {
+                            line_index,
+                            reason: format!(
+                                "trk format only supports 32-bit line ids, wrote id {trk_id} instead"
+                            ),
+                        });
+                    }
+                    trk_id
+                }
+            };
+            lines.push(trk_line);
+        }
+
+        for (trigger_index, trigger) in track.triggers.iter().enumerate() {
+            match trigger {
+                BoshTFTrigger::Zoom {
+                    line,
+                    target,
+                    frames,
+                } => match line_indices.get(line).and_then(|i| lines.get_mut(*i)) {
+                    Some(trk_line) if trk_line.flags.line_type() != TrkLineType::Scenery => {
+                        trk_line.trigger = Some(TrkZoomTrigger {
+                            target: *target,
+                            frames: *frames,
+                        });
+                        features.insert(TrkFeature::IgnorableTrigger.to_string());
+                    }
+                    _ => warnings.push(ConversionWarning::TriggerDropped {
+                        trigger_index,
+                        reason: "trk format only supports triggers on physics lines".to_string(),
+                    }),
+                },
+            }
+        }
+
+        let unsupported_meta = [
+            ("title", track.meta.title.is_some()),
+            ("author", track.meta.author.is_some()),
+            ("description", track.meta.description.is_some()),
+            ("duration", track.meta.duration.is_some()),
+        ];
+        for (field, _) in unsupported_meta.iter().filter(|(_, present)| *present) {
+            warnings.push(ConversionWarning::MetaDropped {
+                field: field.to_string(),
+                reason: "trk format does not support this field".to_string(),
+            });
+        }
+
+        let song = track.meta.song.as_ref().map(|song| TrkSong {
+            name: song.name.clone(),
+            offset_seconds: song.offset_seconds,
+        });
+        if song.is_some() {
+            features.insert(TrkFeature::SongInfo.to_string());
+        }
+
+        let entries: HashMap<String, String> = track.meta.lra_entries.clone().into_iter().collect();
+        let settings = TrkMetaSettings {
+            start_zoom: track.meta.start_zoom,
+            x_gravity: track.meta.gravity_scale.map(|scale| scale.0 as f32),
+            y_gravity: track.meta.gravity_scale.map(|scale| scale.1 as f32),
+            gravity_well_size: track.meta.gravity_well_size,
+            background_color: track.meta.background_color.map(TrkColor::from),
+            line_color: track.meta.line_color.map(TrkColor::from),
+            riders: riders_entry,
+        };
+
+        let meta = if settings == TrkMetaSettings::default() && entries.is_empty() {
+            None
+        } else {
+            Some(TrkMeta { settings, entries })
+        };
+
+        let track = TrkTrack {
             header: TrkHeader {
                 version: 1,
-                features: HashSet::new(),
-                song: None,
-                start_position: Default::default(),
+                features,
+                song,
+                start_position,
             },
+            lines,
+            meta,
+        };
+
+        Ok((track, warnings))
+    }
+}
+
+impl TryFrom<&BoshTFTrack> for TrkTrack {
+    type Error = Error;
+
+    fn try_from(track: &BoshTFTrack) -> Result<TrkTrack> {
+        let (trk, warnings) = TrkTrack::from_boshtf_lossy(track)?;
+        if let Some(warning) = warnings.first() {
+            return Err(anyhow!("error converting boshtf into trk: {warning}"));
+        }
+
+        Ok(trk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+
+    use bosh_rs::Track;
+
+    use super::*;
+
+    fn read_fixture(name: &str) -> TrkTrack {
+        let path = format!("{}/../test/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let file = File::open(&path).unwrap_or_else(|err| panic!("could not open {path}: {err}"));
+
+        TrkTrack::read_from(file).unwrap()
+    }
+
+    #[test]
+    fn reads_song_info() {
+        let trk = read_fixture("2 quicksave.trk");
+
+        assert_eq!(
+            trk.header.song,
+            Some(TrkSong {
+                name: "uh oh.ogg".to_string(),
+                offset_seconds: 0.0,
+            })
+        );
+        // the song must be fully consumed for the rest of the header to line up
+        assert_eq!(trk.header.start_position, Vector2D(0.0, 0.0));
+        assert_eq!(trk.lines.len(), 2266);
+    }
+
+    #[test]
+    fn reads_known_meta_settings() {
+        let trk = read_fixture("2 quicksave.trk");
+        let meta = trk.meta.expect("fixture has a META block");
+
+        assert_eq!(meta.settings.start_zoom, Some(16.0));
+        assert!(meta.entries.is_empty());
+    }
+
+    #[test]
+    fn unknown_meta_entries_survive_round_trip() {
+        let mut entries = HashMap::new();
+        entries.insert("YGRAVITY".to_string(), "0.5".to_string());
+        entries.insert("BGCOLORR".to_string(), "12".to_string());
+        entries.insert("SOMEFORK".to_string(), "a=b".to_string());
+        let settings = TrkMetaSettings::take_from(&mut entries);
+
+        assert_eq!(settings.y_gravity, Some(0.5));
+        // an incomplete color is not understood, so it is kept verbatim
+        assert_eq!(settings.background_color, None);
+        assert_eq!(entries.len(), 2);
+
+        let mut bytes = vec![];
+        TrkMeta { settings, entries }.write_to(&mut bytes).unwrap();
+        let meta = TrkMeta::read_from(bytes.as_slice()).unwrap().unwrap();
+
+        assert_eq!(meta.settings.y_gravity, Some(0.5));
+        assert_eq!(meta.entries.get("BGCOLORR").map(String::as_str), Some("12"));
+        assert_eq!(
+            meta.entries.get("SOMEFORK").map(String::as_str),
+            Some("a=b")
+        );
+    }
+
+    #[test]
+    fn multiple_riders_round_trip() {
+        let sled = |x: f64, velocity: Vector2D, remountable: bool| BoshTFEntity::BoshSled {
+            position: Vector2D(x, 0.0),
+            velocity,
+            remountable,
+            frictionless: false,
+        };
+        let track = BoshTFTrack {
+            meta: Default::default(),
+            entities: vec![
+                sled(0.0, Vector2D(0.4, 0.0), false),
+                sled(10.0, Vector2D(1.5, -2.0), true),
+            ],
             lines: vec![],
-            meta: None,
-        })
+            triggers: vec![],
+            layers: vec![],
+        };
+
+        let (trk, warnings) = TrkTrack::from_boshtf_lossy(&track).unwrap();
+        // LRA makes the first rider remountable too, and only simulates the first one
+        let altered: Vec<usize> = warnings
+            .iter()
+            .filter_map(|warning| match warning {
+                ConversionWarning::EntityAltered { entity_index, .. } => Some(*entity_index),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(warnings.len(), 2);
+        assert_eq!(altered, vec![0, 1]);
+
+        let mut bytes = vec![];
+        trk.write_to(&mut bytes).unwrap();
+        let read_back = BoshTFTrack::from(&TrkTrack::read_from(bytes.as_slice()).unwrap());
+
+        assert_eq!(read_back.entities.len(), 2);
+        assert!(matches!(
+            read_back.entities[1],
+            BoshTFEntity::BoshSled {
+                position: Vector2D(x, _),
+                velocity: Vector2D(vx, vy),
+                remountable: true,
+                ..
+            } if x == 10.0 && vx == 1.5 && vy == -2.0
+        ));
+    }
+
+    #[test]
+    fn unusual_start_velocities_are_reported() {
+        let track = BoshTFTrack {
+            meta: Default::default(),
+            entities: vec![BoshTFEntity::BoshSled {
+                position: Vector2D(0.0, 0.0),
+                velocity: Vector2D(3.0, 1.0),
+                remountable: false,
+                frictionless: false,
+            }],
+            lines: vec![],
+            triggers: vec![],
+            layers: vec![],
+        };
+
+        let (trk, warnings) = TrkTrack::from_boshtf_lossy(&track).unwrap();
+
+        assert!(trk.meta.unwrap().settings.riders.is_some());
+        assert_eq!(warnings.len(), 1);
+        assert!(matches!(
+            warnings[0],
+            ConversionWarning::EntityAltered {
+                entity_index: 0,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn unreadable_riders_entries_fall_back_to_the_header() {
+        for value in ["", ";", "1,2,3", "1,2,x,4,0"] {
+            let mut entries = HashMap::new();
+            entries.insert("RIDERS".to_string(), value.to_string());
+            let settings = TrkMetaSettings::take_from(&mut entries);
+
+            // the entry is not understood, so it is kept verbatim
+            assert!(settings.riders.is_none(), "read riders from {:?}", value);
+            assert_eq!(entries.get("RIDERS").map(String::as_str), Some(value));
+
+            let mut trk = read_fixture("2 quicksave.trk");
+            trk.meta = Some(TrkMeta { settings, entries });
+            let entities = BoshTFTrack::from(&trk).entities;
+
+            assert_eq!(entities.len(), 1);
+            assert!(matches!(
+                entities[0],
+                BoshTFEntity::BoshSled {
+                    position: Vector2D(x, y),
+                    ..
+                } if x == 0.0 && y == 0.0
+            ));
+        }
+    }
+
+    fn json<T: serde::Serialize>(value: &T) -> serde_json::Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    fn write_trk(track: &BoshTFTrack) -> Vec<u8> {
+        let (trk, warnings) = TrkTrack::from_boshtf_lossy(track).unwrap();
+        assert!(warnings.is_empty(), "unexpected warnings: {:?}", warnings);
+
+        let mut bytes = vec![];
+        trk.write_to(&mut bytes).unwrap();
+
+        bytes
+    }
+
+    #[test]
+    fn fixture_round_trips() {
+        let original = BoshTFTrack::from(&read_fixture("2 quicksave.trk"));
+        let bytes = write_trk(&original);
+        let read_back = BoshTFTrack::from(&TrkTrack::read_from(bytes.as_slice()).unwrap());
+
+        // the fixture covers zoom triggers, extended lines, scenery widths and a song
+        assert!(!original.triggers.is_empty());
+        assert!(original
+            .lines
+            .iter()
+            .any(|l| l.line.ends.0.extended || l.line.ends.1.extended));
+        assert!(original.lines.iter().any(|l| l.width.is_some()));
+        assert!(original.meta.song.is_some());
+
+        assert_eq!(json(&original.lines), json(&read_back.lines));
+        assert_eq!(json(&original.triggers), json(&read_back.triggers));
+        assert_eq!(json(&original.entities), json(&read_back.entities));
+        assert_eq!(json(&original.meta), json(&read_back.meta));
+
+        let (original, read_back) = (Track::from(&original), Track::from(&read_back));
+        for frame in [0, 40, 400] {
+            assert_eq!(
+                original.entity_positions_at(frame),
+                read_back.entity_positions_at(frame),
+                "simulation differs at frame {}",
+                frame
+            );
+        }
+    }
+
+    #[test]
+    fn writing_is_deterministic() {
+        let track = BoshTFTrack::from(&read_fixture("2 quicksave.trk"));
+
+        assert_eq!(write_trk(&track), write_trk(&track));
+    }
+
+    #[test]
+    fn song_round_trips_with_multi_byte_length() {
+        let song = TrkSong {
+            name: "a".repeat(200),
+            offset_seconds: 12.5,
+        };
+
+        let mut bytes = vec![];
+        song.write_to(&mut bytes).unwrap();
+
+        assert_eq!(&bytes[..2], &[0xCE, 0x01]);
+        assert_eq!(TrkSong::read_from(bytes.as_slice()).unwrap(), song);
+    }
+
+    #[test]
+    fn songs_without_an_offset_start_at_zero() {
+        for song in ["uh oh.ogg", "uh oh.ogg\r\n", "uh oh.ogg\r\nsoon"] {
+            let mut bytes = vec![song.len() as u8];
+            bytes.extend_from_slice(song.as_bytes());
+
+            assert_eq!(
+                TrkSong::read_from(bytes.as_slice()).unwrap(),
+                TrkSong {
+                    name: "uh oh.ogg".to_string(),
+                    offset_seconds: 0.0,
+                }
+            );
+        }
     }
 }
@@ -0,0 +1,364 @@
+//! Background thread that simulates frames ahead of the playhead, so that they
+//! are already in the frame cache by the time they are needed.
+//!
+//! The thread simulates its own copy of the track, so commands only wait on it
+//! while it stores a batch of frames. Edits to the document restart it.
+
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::sync::{Mutex, Weak};
+use std::thread;
+
+use bosh::serialization::boshtf::BoshTFTrack;
+use bosh_rs::rider::Entity;
+use bosh_rs::Track;
+use serde::Serialize;
+use tauri::Window;
+
+use crate::document::Document;
+use crate::documents::{lock_document, DocumentId};
+use crate::frame_cache::{self, MAX_CACHED_FRAMES};
+
+/// Name of the event that reports how far the simulation has gotten.
+pub const PROGRESS_EVENT: &str = "simulated_up_to";
+
+/// How many frames are simulated before they get stored in the cache.
+const BATCH_SIZE: usize = 40;
+
+#[derive(Debug, Clone, Copy)]
+pub enum SimulationMessage {
+    /// Keep simulating until this frame is cached.
+    SimulateTo(usize),
+    /// The document was edited, so whatever is being simulated is out of date.
+    TrackChanged,
+    /// Stop simulating until the next `SimulateTo`.
+    Cancel,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SimulationProgress {
+    pub document: DocumentId,
+    /// revision of the document that was simulated
+    pub revision: u64,
+    /// every frame before this one is cached
+    pub simulated_up_to: usize,
+    /// frame that the simulation is working towards
+    pub target: usize,
+}
+
+/// Starts the simulation thread for a document, which reports its progress to
+/// `window` and runs until the returned sender or the document is dropped.
+pub fn spawn(
+    window: Window,
+    id: DocumentId,
+    document: Weak<Mutex<Document>>,
+) -> Sender<SimulationMessage> {
+    let (sender, receiver) = mpsc::channel();
+    let report = move |progress: SimulationProgress| {
+        if let Err(err) = window.emit(PROGRESS_EVENT, progress) {
+            eprintln!("{}", err);
+        }
+    };
+    thread::spawn(move || Worker::new(id, document, receiver, report).run());
+
+    sender
+}
+
+struct Worker<R> {
+    id: DocumentId,
+    document: Weak<Mutex<Document>>,
+    receiver: Receiver<SimulationMessage>,
+    target: usize,
+    /// called with the progress every time frames are stored
+    report: R,
+}
+
+/// What interrupted a simulation run.
+enum Interrupt {
+    Restart,
+    Stop,
+    Disconnected,
+}
+
+impl<R: FnMut(SimulationProgress)> Worker<R> {
+    fn new(
+        id: DocumentId,
+        document: Weak<Mutex<Document>>,
+        receiver: Receiver<SimulationMessage>,
+        report: R,
+    ) -> Worker<R> {
+        Worker {
+            id,
+            document,
+            receiver,
+            target: 0,
+            report,
+        }
+    }
+
+    fn run(mut self) {
+        while let Ok(message) = self.receiver.recv() {
+            self.handle(message);
+            loop {
+                let interrupt = match self.simulate() {
+                    Ok(()) => break,
+                    Err(Interrupt::Restart) => self.drain(),
+                    Err(interrupt) => Err(interrupt),
+                };
+                match interrupt {
+                    Ok(()) | Err(Interrupt::Restart) => continue,
+                    Err(Interrupt::Stop) => break,
+                    Err(Interrupt::Disconnected) => return,
+                }
+            }
+        }
+    }
+
+    fn handle(&mut self, message: SimulationMessage) -> Option<Interrupt> {
+        match message {
+            SimulationMessage::SimulateTo(frame) => {
+                self.target = frame.min(MAX_CACHED_FRAMES);
+                None
+            }
+            SimulationMessage::TrackChanged => Some(Interrupt::Restart),
+            SimulationMessage::Cancel => {
+                self.target = 0;
+                Some(Interrupt::Stop)
+            }
+        }
+    }
+
+    /// Handles every message that came in while simulating.
+    fn poll(&mut self) -> Result<(), Interrupt> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(message) => {
+                    if let Some(interrupt) = self.handle(message) {
+                        return Err(interrupt);
+                    }
+                }
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => return Err(Interrupt::Disconnected),
+            }
+        }
+    }
+
+    /// Handles every message that came in before a restart. The restart picks up
+    /// every edit made so far, so any `TrackChanged` left in the queue is dropped
+    /// instead of causing another one.
+    fn drain(&mut self) -> Result<(), Interrupt> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(SimulationMessage::TrackChanged) => {}
+                Ok(message) => {
+                    if let Some(interrupt) = self.handle(message) {
+                        return Err(interrupt);
+                    }
+                }
+                Err(TryRecvError::Empty) => return Ok(()),
+                Err(TryRecvError::Disconnected) => return Err(Interrupt::Disconnected),
+            }
+        }
+    }
+
+    /// Simulates from the end of the cache up to the target.
+    fn simulate(&mut self) -> Result<(), Interrupt> {
+        // only the extras are copied while the document is locked, since building
+        // the track for bosh-rs takes much longer on large tracks
+        let (revision, extras, start, mut previous) = {
+            let document = self.document.upgrade().ok_or(Interrupt::Disconnected)?;
+            let document = lock_document(&document);
+
+            (
+                document.revision(),
+                document.extras().clone(),
+                document.cached_frames(),
+                document.last_cached_frame(),
+            )
+        };
+        let track: Track = (&BoshTFTrack::from(&extras)).into();
+        drop(extras);
+
+        let mut first = start;
+        let mut batch: Vec<Vec<Entity>> = Vec::with_capacity(BATCH_SIZE);
+        let mut frame = start;
+        while frame < self.target {
+            let entities = match &previous {
+                Some(previous) => frame_cache::step(&track, previous),
+                None => track.entity_positions_at(frame),
+            };
+            batch.push(entities.clone());
+            previous = Some(entities);
+            frame += 1;
+
+            // a message can lower the target or stop the run, and the frames that
+            // were already simulated are worth keeping either way
+            let polled = self.poll();
+            if polled.is_err() || batch.len() == BATCH_SIZE || frame >= self.target {
+                self.store(revision, first, std::mem::take(&mut batch))?;
+                first = frame;
+            }
+            polled?;
+        }
+
+        Ok(())
+    }
+
+    fn store(
+        &mut self,
+        revision: u64,
+        first: usize,
+        frames: Vec<Vec<Entity>>,
+    ) -> Result<(), Interrupt> {
+        let simulated_up_to = {
+            let document = self.document.upgrade().ok_or(Interrupt::Disconnected)?;
+            let mut document = lock_document(&document);
+            if document.revision() != revision {
+                return Err(Interrupt::Restart);
+            }
+            document.store_frames(first, frames);
+
+            document.cached_frames()
+        };
+
+        (self.report)(SimulationProgress {
+            document: self.id,
+            revision,
+            simulated_up_to,
+            target: self.target,
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use bosh::serialization::boshtf::BoshTFEntity;
+    use bosh_rs::{Line, Vector2D};
+
+    use super::*;
+
+    fn document_with_rider() -> Arc<Mutex<Document>> {
+        let mut document = Document::default();
+        document.add_entity(BoshTFEntity::BoshSled {
+            velocity: Vector2D(0.4, 0.0),
+            position: Vector2D(0.0, 0.0),
+            remountable: false,
+            frictionless: false,
+        });
+
+        Arc::new(Mutex::new(document))
+    }
+
+    /// A worker that has been asked to simulate up to `target`, and that calls
+    /// `react` with the sender and each progress report before recording it.
+    fn worker(
+        document: &Arc<Mutex<Document>>,
+        target: usize,
+        mut react: impl FnMut(&Sender<SimulationMessage>, &SimulationProgress),
+    ) -> (
+        Worker<impl FnMut(SimulationProgress)>,
+        Rc<RefCell<Vec<SimulationProgress>>>,
+    ) {
+        let (sender, receiver) = mpsc::channel();
+        sender.send(SimulationMessage::SimulateTo(target)).unwrap();
+        let reports = Rc::new(RefCell::new(vec![]));
+        let recorded = Rc::clone(&reports);
+        let report = move |progress: SimulationProgress| {
+            react(&sender, &progress);
+            recorded.borrow_mut().push(progress);
+        };
+
+        let mut worker = Worker::new(1, Arc::downgrade(document), receiver, report);
+        assert!(worker.poll().is_ok());
+
+        (worker, reports)
+    }
+
+    fn reported(reports: &Rc<RefCell<Vec<SimulationProgress>>>) -> Vec<usize> {
+        reports.borrow().iter().map(|p| p.simulated_up_to).collect()
+    }
+
+    #[test]
+    fn frames_are_stored_in_batches() {
+        let document = document_with_rider();
+        let (mut worker, reports) = worker(&document, 100, |_, _| {});
+
+        assert!(worker.simulate().is_ok());
+        assert_eq!(reported(&reports), vec![40, 80, 100]);
+        assert_eq!(lock_document(&document).cached_frames(), 100);
+    }
+
+    #[test]
+    fn cancelling_keeps_the_frames_simulated_so_far() {
+        let document = document_with_rider();
+        let (mut worker, reports) = worker(&document, 100, |sender, progress| {
+            if progress.simulated_up_to == 40 {
+                sender.send(SimulationMessage::Cancel).unwrap();
+            }
+        });
+
+        assert!(matches!(worker.simulate(), Err(Interrupt::Stop)));
+        assert_eq!(reported(&reports), vec![40, 41]);
+        assert_eq!(lock_document(&document).cached_frames(), 41);
+    }
+
+    #[test]
+    fn lowering_the_target_keeps_the_frames_simulated_so_far() {
+        let document = document_with_rider();
+        let (mut worker, reports) = worker(&document, 100, |sender, progress| {
+            if progress.simulated_up_to == 40 {
+                sender.send(SimulationMessage::SimulateTo(20)).unwrap();
+            }
+        });
+
+        assert!(worker.simulate().is_ok());
+        assert_eq!(reported(&reports), vec![40, 41]);
+        assert_eq!(lock_document(&document).cached_frames(), 41);
+    }
+
+    #[test]
+    fn edits_restart_the_simulation() {
+        let document = document_with_rider();
+        let (sender, receiver) = mpsc::channel();
+        let (progress_sender, progress) = mpsc::channel();
+        let report = move |p: SimulationProgress| {
+            let _ = progress_sender.send(p);
+        };
+        let worker = Worker::new(1, Arc::downgrade(&document), receiver, report);
+        let handle = thread::spawn(move || worker.run());
+        lock_document(&document).set_simulation(sender.clone());
+
+        let wait_for = |revision: u64| loop {
+            let p = progress
+                .recv_timeout(Duration::from_secs(10))
+                .expect("the worker stopped reporting progress");
+            if p.revision == revision && p.simulated_up_to == 100 {
+                break;
+            }
+        };
+
+        sender.send(SimulationMessage::SimulateTo(100)).unwrap();
+        wait_for(1);
+
+        // a line right under the rider forgets every frame
+        let line = Line::builder().point(-100.0, 5.0).point(100.0, 5.0).build();
+        let revision = {
+            let mut document = lock_document(&document);
+            document.add_line(line, None).unwrap().revision
+        };
+        assert_eq!(revision, 2);
+        wait_for(revision);
+        assert_eq!(lock_document(&document).cached_frames(), 100);
+
+        drop(sender);
+        drop(document);
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,328 @@
+//! Grid of line ids, for quickly finding the lines in some part of the track.
+
+use std::collections::{HashMap, HashSet};
+
+use bosh_rs::{Line, Vector2D};
+
+/// Width and height of a grid cell, the same as the grid Line Rider uses for physics.
+const CELL_SIZE: f64 = 14.0;
+
+/// Lines that would pass through more cells than this are kept out of the cells,
+/// and checked by every query instead.
+const MAX_LINE_CELLS: i128 = 4096;
+
+type Cell = (i64, i64);
+
+#[derive(Debug, Clone, Default)]
+pub struct LineGrid {
+    cells: HashMap<Cell, Vec<u64>>,
+    /// ends of every line in the grid, by line id
+    ends: HashMap<u64, (Vector2D, Vector2D)>,
+    /// lines that are too long to put in the cells
+    oversized: HashSet<u64>,
+}
+
+impl LineGrid {
+    pub fn insert(&mut self, id: u64, line: &Line) {
+        let ends = (line.ends.0.location, line.ends.1.location);
+        if let Some(old) = self.ends.insert(id, ends) {
+            self.remove_from_cells(id, old);
+        }
+        match cells_along(ends.0, ends.1) {
+            Some(cells) => {
+                for cell in cells {
+                    self.cells.entry(cell).or_default().push(id);
+                }
+            }
+            None => {
+                self.oversized.insert(id);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        if let Some(ends) = self.ends.remove(&id) {
+            self.remove_from_cells(id, ends);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.ends.clear();
+        self.oversized.clear();
+    }
+
+    fn remove_from_cells(&mut self, id: u64, (a, b): (Vector2D, Vector2D)) {
+        if self.oversized.remove(&id) {
+            return;
+        }
+        for cell in cells_along(a, b).unwrap_or_default() {
+            if let Some(ids) = self.cells.get_mut(&cell) {
+                ids.retain(|&other| other != id);
+                if ids.is_empty() {
+                    self.cells.remove(&cell);
+                }
+            }
+        }
+    }
+
+    /// Ids of lines that pass through the rectangle spanned by `min` and `max`.
+    pub fn in_rect(&self, min: Vector2D, max: Vector2D) -> HashSet<u64> {
+        self.candidates(min, max)
+            .into_iter()
+            .filter(|id| {
+                let (a, b) = self.ends[id];
+                segment_intersects_rect(a, b, min, max)
+            })
+            .collect()
+    }
+
+    /// Ids of lines that are at most `radius` away from `point`.
+    pub fn near_point(&self, point: Vector2D, radius: f64) -> HashSet<u64> {
+        let min = Vector2D(point.0 - radius, point.1 - radius);
+        let max = Vector2D(point.0 + radius, point.1 + radius);
+
+        self.candidates(min, max)
+            .into_iter()
+            .filter(|id| {
+                let (a, b) = self.ends[id];
+                distance_to_segment(point, a, b) <= radius
+            })
+            .collect()
+    }
+
+    /// Ids of lines that might pass through the rectangle.
+    fn candidates(&self, min: Vector2D, max: Vector2D) -> HashSet<u64> {
+        let (min_x, min_y) = (cell_index(min.0), cell_index(min.1));
+        let (max_x, max_y) = (cell_index(max.0), cell_index(max.1));
+        if min_x > max_x || min_y > max_y {
+            return HashSet::new();
+        }
+
+        // when zoomed far out, looking at every line is cheaper than every cell
+        let cell_count = span(min_x, max_x).saturating_mul(span(min_y, max_y));
+        if cell_count >= self.ends.len() as i128 {
+            return self.ends.keys().copied().collect();
+        }
+
+        let mut ids = self.oversized.clone();
+        for x in min_x..=max_x {
+            for y in min_y..=max_y {
+                if let Some(cell) = self.cells.get(&(x, y)) {
+                    ids.extend(cell);
+                }
+            }
+        }
+
+        ids
+    }
+}
+
+/// Index of the cell holding `coordinate`. Coordinates past the range of `i64`
+/// end up in the outermost cells.
+fn cell_index(coordinate: f64) -> i64 {
+    (coordinate / CELL_SIZE).floor() as i64
+}
+
+/// How many cells there are from `min` to `max`, including both.
+fn span(min: i64, max: i64) -> i128 {
+    max as i128 - min as i128 + 1
+}
+
+/// Every cell that the segment between `a` and `b` passes through, or `None`
+/// if that is more than [`MAX_LINE_CELLS`].
+fn cells_along(a: Vector2D, b: Vector2D) -> Option<Vec<Cell>> {
+    let (a, b) = if a.0 <= b.0 { (a, b) } else { (b, a) };
+    // a segment crosses at most one cell per column plus one per row
+    let columns = span(cell_index(a.0), cell_index(b.0));
+    let rows = span(cell_index(a.1.min(b.1)), cell_index(a.1.max(b.1)));
+    if columns + rows > MAX_LINE_CELLS {
+        return None;
+    }
+
+    let y_at = |x: f64| {
+        if b.0 == a.0 {
+            a.1
+        } else {
+            a.1 + (x - a.0) * (b.1 - a.1) / (b.0 - a.0)
+        }
+    };
+
+    let mut cells = vec![];
+    for x in cell_index(a.0)..=cell_index(b.0) {
+        // the part of the segment that is inside of this column
+        let left = (x as f64 * CELL_SIZE).max(a.0);
+        let right = ((x as f64 + 1.0) * CELL_SIZE).min(b.0);
+        let (top, bottom) = if b.0 == a.0 {
+            (a.1.min(b.1), a.1.max(b.1))
+        } else {
+            let (y1, y2) = (y_at(left), y_at(right));
+            (y1.min(y2), y1.max(y2))
+        };
+
+        for y in cell_index(top)..=cell_index(bottom) {
+            cells.push((x, y));
+        }
+    }
+
+    Some(cells)
+}
+
+/// Whether any part of the segment between `a` and `b` is inside of the rectangle,
+/// by clipping the segment to the rectangle (Liang–Barsky).
+fn segment_intersects_rect(a: Vector2D, b: Vector2D, min: Vector2D, max: Vector2D) -> bool {
+    let delta = (b.0 - a.0, b.1 - a.1);
+    let mut t0 = 0.0_f64;
+    let mut t1 = 1.0_f64;
+
+    for (p, q) in [
+        (-delta.0, a.0 - min.0),
+        (delta.0, max.0 - a.0),
+        (-delta.1, a.1 - min.1),
+        (delta.1, max.1 - a.1),
+    ] {
+        if p == 0.0 {
+            if q < 0.0 {
+                return false;
+            }
+        } else {
+            let t = q / p;
+            if p < 0.0 {
+                t0 = t0.max(t);
+            } else {
+                t1 = t1.min(t);
+            }
+            if t0 > t1 {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+fn distance_to_segment(point: Vector2D, a: Vector2D, b: Vector2D) -> f64 {
+    let delta = (b.0 - a.0, b.1 - a.1);
+    let length_squared = delta.0 * delta.0 + delta.1 * delta.1;
+    let t = if length_squared == 0.0 {
+        0.0
+    } else {
+        (((point.0 - a.0) * delta.0 + (point.1 - a.1) * delta.1) / length_squared).clamp(0.0, 1.0)
+    };
+    let closest = (a.0 + t * delta.0, a.1 + t * delta.1);
+
+    ((point.0 - closest.0).powi(2) + (point.1 - closest.1).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(a: (f64, f64), b: (f64, f64)) -> Line {
+        Line::builder().point(a.0, a.1).point(b.0, b.1).build()
+    }
+
+    fn ids(ids: HashSet<u64>) -> Vec<u64> {
+        let mut ids: Vec<u64> = ids.into_iter().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn clipping_finds_segments_through_the_rect() {
+        let (min, max) = (Vector2D(0.0, 0.0), Vector2D(10.0, 10.0));
+        let intersects = |a: (f64, f64), b: (f64, f64)| {
+            segment_intersects_rect(Vector2D(a.0, a.1), Vector2D(b.0, b.1), min, max)
+        };
+
+        // inside, crossing through without an end inside, and touching a corner
+        assert!(intersects((2.0, 2.0), (3.0, 3.0)));
+        assert!(intersects((-5.0, 5.0), (15.0, 5.0)));
+        assert!(intersects((-5.0, 5.0), (5.0, -5.0)));
+        assert!(intersects((10.0, 10.0), (20.0, 20.0)));
+        // a single point
+        assert!(intersects((5.0, 5.0), (5.0, 5.0)));
+
+        // beside, and diagonally past a corner
+        assert!(!intersects((11.0, 0.0), (11.0, 10.0)));
+        assert!(!intersects((-5.0, 4.0), (4.0, -5.1)));
+        assert!(!intersects((20.0, 20.0), (20.0, 20.0)));
+    }
+
+    #[test]
+    fn distance_is_measured_to_the_closest_part_of_the_segment() {
+        let (a, b) = (Vector2D(0.0, 0.0), Vector2D(10.0, 0.0));
+
+        assert_eq!(distance_to_segment(Vector2D(5.0, 3.0), a, b), 3.0);
+        assert_eq!(distance_to_segment(Vector2D(13.0, 4.0), a, b), 5.0);
+        assert_eq!(distance_to_segment(Vector2D(3.0, 4.0), a, a), 5.0);
+    }
+
+    #[test]
+    fn cells_follow_the_segment() {
+        let cells = |a: (f64, f64), b: (f64, f64)| {
+            let mut cells = cells_along(Vector2D(a.0, a.1), Vector2D(b.0, b.1)).unwrap();
+            cells.sort_unstable();
+            cells
+        };
+
+        assert_eq!(cells((1.0, 1.0), (30.0, 1.0)), [(0, 0), (1, 0), (2, 0)]);
+        assert_eq!(cells((1.0, 30.0), (1.0, 1.0)), [(0, 0), (0, 1), (0, 2)]);
+        assert_eq!(cells((-1.0, -1.0), (1.0, 1.0)), [(-1, -1), (-1, 0), (0, 0)]);
+        assert_eq!(cells((1.0, 1.0), (27.0, 20.0)), [(0, 0), (1, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn long_lines_skip_the_cells() {
+        let mut grid = LineGrid::default();
+        // enough short lines that queries go through the cells
+        for id in 10..20 {
+            let x = id as f64 * 100.0;
+            grid.insert(id, &line((x, 100.0), (x + 10.0, 110.0)));
+        }
+        grid.insert(1, &line((0.0, 0.0), (1e9, 0.0)));
+        grid.insert(2, &line((0.0, 0.0), (f64::INFINITY, 5.0)));
+        assert_eq!(grid.oversized.len(), 2);
+
+        let found = grid.near_point(Vector2D(5e8, 1.0), 2.0);
+        assert_eq!(ids(found), [1]);
+
+        grid.remove(1);
+        grid.remove(2);
+        assert!(grid.oversized.is_empty());
+        assert!(grid.near_point(Vector2D(5e8, 1.0), 2.0).is_empty());
+    }
+
+    #[test]
+    fn huge_queries_do_not_overflow() {
+        let mut grid = LineGrid::default();
+        grid.insert(1, &line((0.0, 0.0), (10.0, 10.0)));
+        grid.insert(2, &line((100.0, 0.0), (110.0, 10.0)));
+
+        let all = grid.in_rect(Vector2D(-1e20, -1e20), Vector2D(1e20, 1e20));
+        assert_eq!(ids(all), [1, 2]);
+
+        let all = grid.in_rect(
+            Vector2D(f64::NEG_INFINITY, f64::NEG_INFINITY),
+            Vector2D(f64::INFINITY, f64::INFINITY),
+        );
+        assert_eq!(ids(all), [1, 2]);
+    }
+
+    #[test]
+    fn queries_only_find_nearby_lines() {
+        let mut grid = LineGrid::default();
+        for id in 0..10 {
+            let x = id as f64 * 100.0;
+            grid.insert(id, &line((x, 0.0), (x + 10.0, 10.0)));
+        }
+
+        let found = grid.in_rect(Vector2D(95.0, -5.0), Vector2D(105.0, 5.0));
+        assert_eq!(ids(found), [1]);
+        let found = grid.near_point(Vector2D(200.0, 5.0), 4.0);
+        assert_eq!(ids(found), [2]);
+
+        grid.insert(2, &line((500.0, 500.0), (510.0, 510.0)));
+        assert!(grid.near_point(Vector2D(200.0, 5.0), 4.0).is_empty());
+    }
+}
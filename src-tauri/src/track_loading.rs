@@ -1,43 +1,120 @@
-use std::fs::File;
+use std::fs;
 use std::path::PathBuf;
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{Context, Result};
 use read_from::ReadFrom;
+use serde::Serialize;
+use serde_json::Value;
 
 use crate::serialization;
 use crate::serialization::boshtf::BoshTFTrack;
 use crate::serialization::lrcom::LRComTrack;
+use crate::serialization::TrackFormat;
 
-pub fn load(file_path: &str) -> Result<BoshTFTrack> {
-    // first to parse based on its extension
-    let extension = get_extension(PathBuf::from(file_path));
+/// The checks used to recognize a track's format from its contents.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize)]
+pub enum Detector {
+    /// The file starts with `TRK\xF2`.
+    #[serde(rename = "trkMagic")]
+    TrkMagic,
+    /// The file is a JSON object with `linesArray` or `startPosition`.
+    #[serde(rename = "lrcomKeys")]
+    LRComKeys,
+    /// The file is a JSON object with `entities` and `lines`.
+    #[serde(rename = "boshtfKeys")]
+    BoshTFKeys,
+}
+
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Detection {
+    pub format: TrackFormat,
+    pub detector: Detector,
+    /// The format suggested by the file's extension, if any.
+    pub extension_hint: Option<TrackFormat>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LoadedTrack {
+    #[serde(flatten)]
+    pub track: BoshTFTrack,
+    pub detection: Detection,
+}
 
-    let file =
-        File::open(file_path).with_context(|| format!("error while opening file {}", file_path))?;
+pub fn load(file_path: &str) -> Result<LoadedTrack> {
+    // the extension is only used to decide which format to check first
+    let extension_hint =
+        get_extension(PathBuf::from(file_path)).and_then(|ext| TrackFormat::from_extension(&ext));
+
+    let contents =
+        fs::read(file_path).with_context(|| format!("error while opening file {}", file_path))?;
+    let detection = detect_format(&contents, extension_hint)
+        .with_context(|| format!("could not recognize the format of {}", file_path))?;
+
+    let mut track = match detection.format {
+        TrackFormat::BoshTF => deserialize_boshtf(&contents),
+        TrackFormat::LRCom => deserialize_lrcom(&contents),
+        TrackFormat::Trk => deserialize_lra(&contents),
+    }?;
+    track.assign_line_ids();
+
+    Ok(LoadedTrack { track, detection })
+}
 
-    // todo - do not rely on extension
-    match extension.as_deref() {
-        Some("boshtf") => deserialize_boshtf(file),
-        Some("track.json") => deserialize_lrcom(file),
-        Some("trk") => deserialize_lra(file),
-        Some(other) => Err(anyhow!("not a recognizable extension: {}", other)),
-        None => Err(anyhow!("needs an extension: {}", file_path)),
+/// Recognizes the format of a track from its contents, checking the format that
+/// the extension suggests first.
+fn detect_format(contents: &[u8], extension_hint: Option<TrackFormat>) -> Option<Detection> {
+    let json: Option<Value> = serde_json::from_slice(contents).ok();
+
+    let mut candidates = vec![TrackFormat::Trk, TrackFormat::BoshTF, TrackFormat::LRCom];
+    if let Some(hint) = extension_hint {
+        candidates.retain(|format| *format != hint);
+        candidates.insert(0, hint);
+    }
+
+    candidates.into_iter().find_map(|format| {
+        detect(format, contents, json.as_ref()).map(|detector| Detection {
+            format,
+            detector,
+            extension_hint,
+        })
+    })
+}
+
+fn detect(format: TrackFormat, contents: &[u8], json: Option<&Value>) -> Option<Detector> {
+    let has_key = |key: &str| {
+        json.and_then(Value::as_object)
+            .map(|obj| obj.contains_key(key))
+            .unwrap_or(false)
+    };
+
+    match format {
+        TrackFormat::Trk => contents
+            .starts_with(&[b'T', b'R', b'K', 0xF2])
+            .then(|| Detector::TrkMagic),
+        TrackFormat::LRCom => {
+            (has_key("linesArray") || has_key("startPosition")).then(|| Detector::LRComKeys)
+        }
+        TrackFormat::BoshTF => {
+            (has_key("entities") && has_key("lines")).then(|| Detector::BoshTFKeys)
+        }
     }
 }
 
-fn deserialize_boshtf(f: File) -> Result<BoshTFTrack> {
-    serde_json::from_reader(f).context("error while parsing file as boshtf format")
+fn deserialize_boshtf(contents: &[u8]) -> Result<BoshTFTrack> {
+    serde_json::from_slice(contents).context("error while parsing file as boshtf format")
 }
 
-fn deserialize_lrcom(f: File) -> Result<BoshTFTrack> {
+fn deserialize_lrcom(contents: &[u8]) -> Result<BoshTFTrack> {
     let track: LRComTrack =
-        serde_json::from_reader(f).context("error while parsing file as lr.com format")?;
+        serde_json::from_slice(contents).context("error while parsing file as lr.com format")?;
 
     BoshTFTrack::try_from(&track).context("error converting lr.com to local format")
 }
 
-fn deserialize_lra(f: File) -> Result<BoshTFTrack> {
-    let trk = serialization::trk::TrkTrack::read_from(f)
+fn deserialize_lra(contents: &[u8]) -> Result<BoshTFTrack> {
+    let trk = serialization::trk::TrkTrack::read_from(contents)
         .context("error while parsing file as lr-a format")?;
 
     Ok(BoshTFTrack::from(&trk))
@@ -56,3 +133,77 @@ fn get_extension(mut path: PathBuf) -> Option<String> {
 
     Some(full_extension)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRK: &[u8] = &[b'T', b'R', b'K', 0xF2, 1, 0, 0];
+    const BOSHTF: &[u8] = br#"{"meta": {}, "entities": [], "lines": []}"#;
+    const LRCOM: &[u8] = br#"{"label": "", "startPosition": {"x": 0, "y": 0}, "linesArray": []}"#;
+
+    fn detected(contents: &[u8], hint: Option<TrackFormat>) -> Option<(TrackFormat, Detector)> {
+        detect_format(contents, hint).map(|detection| (detection.format, detection.detector))
+    }
+
+    #[test]
+    fn detects_formats_without_a_hint() {
+        assert_eq!(
+            detected(TRK, None),
+            Some((TrackFormat::Trk, Detector::TrkMagic))
+        );
+        assert_eq!(
+            detected(BOSHTF, None),
+            Some((TrackFormat::BoshTF, Detector::BoshTFKeys))
+        );
+        assert_eq!(
+            detected(LRCOM, None),
+            Some((TrackFormat::LRCom, Detector::LRComKeys))
+        );
+    }
+
+    #[test]
+    fn contents_win_over_a_wrong_hint() {
+        assert_eq!(
+            detected(BOSHTF, Some(TrackFormat::Trk)),
+            Some((TrackFormat::BoshTF, Detector::BoshTFKeys))
+        );
+        assert_eq!(
+            detected(TRK, Some(TrackFormat::LRCom)),
+            Some((TrackFormat::Trk, Detector::TrkMagic))
+        );
+
+        let detection = detect_format(LRCOM, Some(TrackFormat::BoshTF)).unwrap();
+        assert_eq!(detection.format, TrackFormat::LRCom);
+        assert_eq!(detection.extension_hint, Some(TrackFormat::BoshTF));
+    }
+
+    #[test]
+    fn renamed_files_get_no_hint() {
+        // a track saved as `track.txt` or `track.json` has no usable hint
+        for name in ["track.txt", "track.json", "track"] {
+            let hint = get_extension(PathBuf::from(name))
+                .and_then(|ext| TrackFormat::from_extension(&ext));
+            assert_eq!(hint, None, "{} should not have a hint", name);
+        }
+
+        assert_eq!(
+            detected(BOSHTF, None),
+            Some((TrackFormat::BoshTF, Detector::BoshTFKeys))
+        );
+    }
+
+    #[test]
+    fn multi_part_extensions_are_hints() {
+        let hint = get_extension(PathBuf::from("saves/my.track.json"))
+            .and_then(|ext| TrackFormat::from_extension(&ext));
+
+        assert_eq!(hint, Some(TrackFormat::LRCom));
+    }
+
+    #[test]
+    fn unrecognized_contents_are_rejected() {
+        assert_eq!(detected(b"not a track", Some(TrackFormat::Trk)), None);
+        assert_eq!(detected(br#"{"lines": []}"#, None), None);
+    }
+}
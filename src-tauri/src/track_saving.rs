@@ -0,0 +1,209 @@
+use std::fs::{self, File};
+use std::io::Write;
+
+use anyhow::{Context, Result};
+use read_from::WriteTo;
+
+use crate::serialization::boshtf::BoshTFTrack;
+use crate::serialization::lrcom::LRComTrack;
+use crate::serialization::trk::TrkTrack;
+use crate::serialization::{ConversionWarning, TrackFormat};
+
+/// Saves the track to `file_path`, returning anything that the format could not represent.
+///
+/// The track is fully serialized before anything is written, and then written
+/// next to `file_path` and renamed over it, so a failed save never leaves a
+/// half-written or emptied file behind.
+pub fn save(
+    file_path: &str,
+    format: TrackFormat,
+    track: &BoshTFTrack,
+) -> Result<Vec<ConversionWarning>> {
+    let mut bytes = vec![];
+    let warnings = match format {
+        TrackFormat::BoshTF => serialize_boshtf(&mut bytes, track),
+        TrackFormat::LRCom => serialize_lrcom(&mut bytes, track),
+        TrackFormat::Trk => serialize_lra(&mut bytes, track),
+    }?;
+
+    let temp_path = format!("{}.tmp", file_path);
+    let written = write_file(&temp_path, &bytes).and_then(|()| {
+        fs::rename(&temp_path, file_path)
+            .with_context(|| format!("error while replacing file {}", file_path))
+    });
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    written?;
+
+    Ok(warnings)
+}
+
+fn write_file(path: &str, bytes: &[u8]) -> Result<()> {
+    let mut file =
+        File::create(path).with_context(|| format!("error while creating file {}", path))?;
+    file.write_all(bytes)
+        .with_context(|| format!("error while writing file {}", path))?;
+    file.sync_all()
+        .with_context(|| format!("error while flushing file {}", path))
+}
+
+fn serialize_boshtf(f: &mut Vec<u8>, track: &BoshTFTrack) -> Result<Vec<ConversionWarning>> {
+    serde_json::to_writer(f, track).context("error while writing file as boshtf format")?;
+
+    Ok(vec![])
+}
+
+fn serialize_lrcom(f: &mut Vec<u8>, track: &BoshTFTrack) -> Result<Vec<ConversionWarning>> {
+    let (track, warnings) =
+        LRComTrack::from_boshtf_lossy(track).context("error converting local format to lr.com")?;
+    serde_json::to_writer(f, &track).context("error while writing file as lr.com format")?;
+
+    Ok(warnings)
+}
+
+fn serialize_lra(f: &mut Vec<u8>, track: &BoshTFTrack) -> Result<Vec<ConversionWarning>> {
+    let (track, warnings) =
+        TrkTrack::from_boshtf_lossy(track).context("error converting local format to lr-a")?;
+    track
+        .write_to(f)
+        .context("error while writing file as lr-a format")?;
+
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::{Path, PathBuf};
+    use std::process;
+
+    use bosh_rs::{Line, Vector2D};
+
+    use crate::serialization::boshtf::{BoshTFEntity, BoshTFLine, BoshTFLineType};
+    use crate::track_loading;
+
+    use super::*;
+
+    const FORMATS: [TrackFormat; 3] = [TrackFormat::BoshTF, TrackFormat::LRCom, TrackFormat::Trk];
+
+    /// An empty directory that only this test writes to.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("bosh-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        dir
+    }
+
+    fn path_for(dir: &Path, format: TrackFormat) -> String {
+        let path = dir.join(format!("track.{}", format.extension()));
+
+        path.to_str().unwrap().to_string()
+    }
+
+    fn track() -> BoshTFTrack {
+        let line = |id: u64, line_type: BoshTFLineType| BoshTFLine {
+            id: Some(id),
+            width: None,
+            color: None,
+            layer: None,
+            line: Line::builder()
+                .point(id as f64 * 20.0, 10.0)
+                .point(id as f64 * 20.0 + 15.0, 12.5)
+                .line_type(line_type)
+                .build(),
+        };
+
+        BoshTFTrack {
+            entities: vec![BoshTFEntity::BoshSled {
+                position: Vector2D(0.0, 0.0),
+                velocity: Vector2D(0.4, 0.0),
+                remountable: false,
+                frictionless: false,
+            }],
+            lines: vec![
+                line(0, BoshTFLineType::Normal),
+                line(1, BoshTFLineType::Accelerate { amount: 1 }),
+                line(2, BoshTFLineType::Normal),
+            ],
+            ..Default::default()
+        }
+    }
+
+    fn json<T: serde::Serialize>(value: &T) -> serde_json::Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    #[test]
+    fn saved_tracks_read_back_the_same() {
+        let dir = temp_dir("round-trip");
+        let original = track();
+        for format in FORMATS {
+            let path = path_for(&dir, format);
+            let warnings = save(&path, format, &original).unwrap();
+            assert!(warnings.is_empty(), "{:?}: {:?}", format, warnings);
+
+            let loaded = track_loading::load(&path).unwrap();
+            assert_eq!(loaded.detection.format, format);
+            assert_eq!(
+                json(&loaded.track.lines),
+                json(&original.lines),
+                "{:?}",
+                format
+            );
+            assert_eq!(
+                json(&loaded.track.entities),
+                json(&original.entities),
+                "{:?}",
+                format
+            );
+            assert!(!PathBuf::from(format!("{}.tmp", path)).exists());
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_serialization_leaves_the_file_alone() {
+        let dir = temp_dir("failed-serialization");
+        let path = path_for(&dir, TrackFormat::Trk);
+        save(&path, TrackFormat::Trk, &track()).unwrap();
+        let before = fs::read(&path).unwrap();
+
+        // trk metadata entries are limited to i16::MAX bytes
+        let mut too_long = track();
+        too_long
+            .meta
+            .lra_entries
+            .insert("SOMEFORK".to_string(), "x".repeat(40_000));
+        assert!(save(&path, TrackFormat::Trk, &too_long).is_err());
+
+        assert_eq!(fs::read(&path).unwrap(), before);
+        assert!(!PathBuf::from(format!("{}.tmp", path)).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_writes_leave_the_file_alone() {
+        let dir = temp_dir("failed-write");
+        for format in FORMATS {
+            let path = path_for(&dir, format);
+            save(&path, format, &track()).unwrap();
+            let before = fs::read(&path).unwrap();
+
+            // boshtf and lr.com serialize any track, so the write is made to fail instead
+            let temp_path = format!("{}.tmp", path);
+            fs::create_dir(&temp_path).unwrap();
+            let mut changed = track();
+            changed.lines.pop();
+            assert!(save(&path, format, &changed).is_err(), "{:?}", format);
+
+            assert_eq!(fs::read(&path).unwrap(), before, "{:?}", format);
+            fs::remove_dir(&temp_path).unwrap();
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
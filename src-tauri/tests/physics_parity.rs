@@ -0,0 +1,268 @@
+//! Compares bosh-rs physics against reference frame dumps taken from Line Rider
+//! Advanced, so that regressions in faithfulness show up as test failures.
+//!
+//! Each track in `test/` is checked against `test/parity/<track file>.csv`, in the
+//! format that `bosh-cli simulate` writes, and skipped until it has one. Set `BOSH_PARITY_EPSILON` to change
+//! how far a coordinate may be off before it counts as a divergence.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use bosh::frame_dump::{self, DumpFormat, DumpedPoint, FrameWriter};
+use bosh::serialization::TrackFormat;
+use bosh::track_loading;
+use bosh_rs::Track;
+
+const DEFAULT_EPSILON: f64 = 1e-9;
+
+fn test_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("../test")
+}
+
+fn epsilon() -> f64 {
+    match std::env::var("BOSH_PARITY_EPSILON") {
+        Ok(epsilon) => epsilon
+            .parse()
+            .expect("BOSH_PARITY_EPSILON is not a number"),
+        Err(_) => DEFAULT_EPSILON,
+    }
+}
+
+fn load_track(path: &Path) -> Result<Track> {
+    let path = path.to_str().context("track path is not valid unicode")?;
+    let loaded = track_loading::load(path)?;
+
+    Ok((&loaded.track).into())
+}
+
+/// Simulates every frame that appears in `frames`.
+fn simulate(track: &Track, frames: &BTreeSet<usize>) -> Vec<DumpedPoint> {
+    frames
+        .iter()
+        .flat_map(|&frame| frame_dump::dumped_points(frame, &track.entity_positions_at(frame)))
+        .collect()
+}
+
+/// Where a simulation first stopped matching its reference.
+#[derive(Debug, PartialEq)]
+struct Divergence {
+    frame: usize,
+    entity: usize,
+    point: String,
+    /// what differed, like "x" or "previous y", with both values
+    detail: String,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "frame {}, entity {}, point {}: {}",
+            self.frame, self.entity, self.point, self.detail
+        )
+    }
+}
+
+/// The divergence on the earliest frame, if any point is further than
+/// `epsilon` from its reference.
+fn first_divergence(
+    expected: &[DumpedPoint],
+    actual: &[DumpedPoint],
+    epsilon: f64,
+) -> Option<Divergence> {
+    let actual: HashMap<(usize, usize, &str), &DumpedPoint> = actual
+        .iter()
+        .map(|point| ((point.frame, point.entity, point.point.as_str()), point))
+        .collect();
+
+    let mut expected: Vec<&DumpedPoint> = expected.iter().collect();
+    expected.sort_by_key(|point| point.frame);
+
+    expected.into_iter().find_map(|point| {
+        let divergence = |detail: String| Divergence {
+            frame: point.frame,
+            entity: point.entity,
+            point: point.point.clone(),
+            detail,
+        };
+
+        let simulated = match actual.get(&(point.frame, point.entity, point.point.as_str())) {
+            Some(simulated) => simulated,
+            None => return Some(divergence("missing from the simulation".to_string())),
+        };
+        let coordinates = [
+            ("x", point.location.0, simulated.location.0),
+            ("y", point.location.1, simulated.location.1),
+            (
+                "previous x",
+                point.previous_location.0,
+                simulated.previous_location.0,
+            ),
+            (
+                "previous y",
+                point.previous_location.1,
+                simulated.previous_location.1,
+            ),
+        ];
+        coordinates
+            .iter()
+            .find(|(_, expected, actual)| {
+                let off = (expected - actual).abs();
+                off.is_nan() || off > epsilon
+            })
+            .map(|(name, expected, actual)| {
+                divergence(format!(
+                    "{} is {} but the reference has {} (epsilon {})",
+                    name, actual, expected, epsilon
+                ))
+            })
+    })
+}
+
+/// The tracks in `test/`, which get checked once they have a reference dump.
+fn fixture_tracks() -> Vec<PathBuf> {
+    let mut tracks: Vec<PathBuf> = fs::read_dir(test_dir())
+        .expect("error while listing test tracks")
+        .map(|entry| entry.expect("error while listing test tracks").path())
+        .filter(|path| {
+            let name = path.file_name().and_then(|name| name.to_str());
+            path.is_file() && name.and_then(TrackFormat::from_extension).is_some()
+        })
+        .collect();
+    tracks.sort();
+
+    tracks
+}
+
+fn dump_for(track: &Path) -> PathBuf {
+    let name = track.file_name().unwrap_or_default().to_string_lossy();
+
+    test_dir().join("parity").join(format!("{}.csv", name))
+}
+
+fn check_reference(track: &Path, dump: &Path, epsilon: f64) -> Result<Option<Divergence>> {
+    let file =
+        File::open(dump).with_context(|| format!("error while opening {}", dump.display()))?;
+    let track = load_track(track).context("error while loading the track")?;
+
+    let expected = frame_dump::read_csv(BufReader::new(file))
+        .with_context(|| format!("error while reading {}", dump.display()))?;
+    let frames: BTreeSet<usize> = expected.iter().map(|point| point.frame).collect();
+
+    Ok(first_divergence(
+        &expected,
+        &simulate(&track, &frames),
+        epsilon,
+    ))
+}
+
+#[test]
+fn matches_reference_dumps() {
+    let tracks = fixture_tracks();
+    assert!(!tracks.is_empty(), "no tracks in test/");
+
+    let epsilon = epsilon();
+    let failures: Vec<String> = tracks
+        .iter()
+        .filter_map(|track| {
+            let dump = dump_for(track);
+            if !dump.exists() {
+                // dumps have to come from Line Rider Advanced, see test/parity/README.md
+                eprintln!(
+                    "no reference dump for {}, skipping it until {} is checked in",
+                    track.display(),
+                    dump.display()
+                );
+                return None;
+            }
+
+            match check_reference(track, &dump, epsilon) {
+                Ok(None) => None,
+                Ok(Some(divergence)) => Some(format!("{}: {}", track.display(), divergence)),
+                Err(err) => Some(format!("{}: {:#}", track.display(), err)),
+            }
+        })
+        .collect();
+
+    assert!(
+        failures.is_empty(),
+        "simulation diverged from the reference:\n{}",
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn csv_dumps_round_trip() {
+    let track = load_track(&test_dir().join("2 quicksave.trk")).unwrap();
+    let frames: BTreeSet<usize> = (0..120).collect();
+    let simulated = simulate(&track, &frames);
+
+    let mut writer = FrameWriter::new(Vec::new(), DumpFormat::Csv).unwrap();
+    for &frame in &frames {
+        writer
+            .write_frame(frame, &track.entity_positions_at(frame))
+            .unwrap();
+    }
+    let csv = writer.finish().unwrap();
+    let read = frame_dump::read_csv(csv.as_slice()).unwrap();
+
+    assert_eq!(read, simulated);
+    assert_eq!(first_divergence(&read, &simulated, 0.0), None);
+}
+
+#[test]
+fn lra_point_names_are_read_as_bosh_names() {
+    let csv = "frame,entity,point,x,y,previous_x,previous_y\n\
+               0,0,SledTL,1,2,3,4\n\
+               0,0,BodyHandLeft,1,2,3,4\n\
+               0,0,BoshButt,1,2,3,4\n";
+    let read = frame_dump::read_csv(csv.as_bytes()).unwrap();
+    let names: Vec<&str> = read.iter().map(|point| point.point.as_str()).collect();
+
+    assert_eq!(names, ["SledPeg", "BoshLeftHand", "BoshButt"]);
+}
+
+fn point(frame: usize, name: &str, x: f64) -> DumpedPoint {
+    DumpedPoint {
+        frame,
+        entity: 0,
+        point: name.to_string(),
+        location: (x, 0.0),
+        previous_location: (x, 0.0),
+    }
+}
+
+#[test]
+fn divergence_within_epsilon_is_ignored() {
+    let expected = [point(0, "SledPeg", 1.0), point(1, "SledPeg", 2.0)];
+    let actual = [point(0, "SledPeg", 1.0), point(1, "SledPeg", 2.0 + 1e-12)];
+
+    assert_eq!(first_divergence(&expected, &actual, 1e-9), None);
+}
+
+#[test]
+fn earliest_divergent_frame_is_reported() {
+    let expected = [point(5, "SledPeg", 1.0), point(2, "SledTail", 1.0)];
+    let actual = [point(5, "SledPeg", 3.0), point(2, "SledTail", 1.5)];
+
+    let divergence = first_divergence(&expected, &actual, 1e-9).unwrap();
+    assert_eq!(
+        (divergence.frame, divergence.point.as_str()),
+        (2, "SledTail")
+    );
+    assert!(divergence.detail.starts_with("x is 1.5"));
+}
+
+#[test]
+fn missing_points_diverge() {
+    let expected = [point(0, "SledPeg", 1.0), point(0, "SledTail", 1.0)];
+    let actual = [point(0, "SledPeg", 1.0)];
+
+    let divergence = first_divergence(&expected, &actual, 1e-9).unwrap();
+    assert_eq!(divergence.point, "SledTail");
+    assert_eq!(divergence.detail, "missing from the simulation");
+}
@@ -1,7 +1,18 @@
 import { JSX, useContext } from 'solid-js';
 import HoldableButton from './HoldableButton';
 import { GameContext } from '../rust_interop/game_manager';
-import { openSingleFilePicker } from './filePicker';
+import { openSaveFilePicker, openSingleFilePicker } from './filePicker';
+import { TrackFormat } from '../rust_interop/tauri_types';
+
+function formatFromPath(path: string): TrackFormat {
+	if (path.endsWith('.track.json')) {
+		return 'track.json';
+	}
+	if (path.endsWith('.trk')) {
+		return 'trk';
+	}
+	return 'boshtf';
+}
 
 function ButtonBar(props: { style?: JSX.CSSProperties }): JSX.Element {
 	const gameManager = useContext(GameContext);
@@ -32,6 +43,26 @@ function ButtonBar(props: { style?: JSX.CSSProperties }): JSX.Element {
 			>
 				Load Track
 			</button>
+			<button
+				onClick={() => {
+					openSaveFilePicker()
+						.then(async (path) => {
+							if (!path) {
+								return;
+							}
+							const warnings = await gameManager.saveTrack(
+								path,
+								formatFromPath(path),
+							);
+							warnings.forEach((warning) => console.warn(warning));
+						})
+						.catch((err) => {
+							console.error(err);
+						});
+				}}
+			>
+				Save Track
+			</button>
 		</div>
 	);
 }
@@ -16,3 +16,18 @@ export async function openSingleFilePicker(): Promise<PickFileResult> {
 	}
 	return fileName;
 }
+
+export async function openSaveFilePicker(): Promise<PickFileResult> {
+	const fileName = await dialog.save({
+		title: 'save track',
+		filters: [
+			{ name: 'bosh track', extensions: ['boshtf'] },
+			{ name: 'linerider.com track', extensions: ['track.json'] },
+			{ name: 'LRA track', extensions: ['trk'] },
+		],
+	});
+	if (!fileName) {
+		return;
+	}
+	return fileName;
+}
@@ -1,17 +1,29 @@
 import { createContext, createSignal } from 'solid-js';
-import { EntityStart, Line, RuntimeEntity, Track } from './tauri_types';
+import {
+	ConversionWarning,
+	EntityStart,
+	Line,
+	LineDelta,
+	RuntimeEntity,
+	TrackFormat,
+	Track,
+} from './tauri_types';
 import {
 	addEntity,
 	addLine,
 	entityPositionsAt,
+	linesSince,
 	loadTrack,
 	removeLine,
+	saveTrack,
 } from './tauri_commands';
 
 export class GameManager {
 	#frameSignal = createSignal<number>(0);
 	#linesSignal = createSignal<Line[]>([]);
 	#entitiesSignal = createSignal<RuntimeEntity[]>([]);
+	#document?: number;
+	#revision = 0;
 
 	lines(): Line[] {
 		return this.#linesSignal[0]();
@@ -28,6 +40,8 @@ export class GameManager {
 	async loadTrack(path: string): Promise<Track> {
 		const track = await loadTrack(path);
 		this.#setLines(track.lines);
+		this.#document = track.document;
+		this.#revision = track.revision;
 
 		const entities = await entityPositionsAt(this.frame());
 		this.#setEntities(entities);
@@ -35,6 +49,13 @@ export class GameManager {
 		return track;
 	}
 
+	async saveTrack(
+		path: string,
+		format: TrackFormat,
+	): Promise<ConversionWarning[]> {
+		return await saveTrack(path, format);
+	}
+
 	async setFrame(frame: number) {
 		const entities = await entityPositionsAt(frame);
 		this.#setEntities(entities);
@@ -42,13 +63,16 @@ export class GameManager {
 	}
 
 	async addLine(line: Line) {
-		const lines = await addLine(line);
-		this.#setLines(lines);
+		await addLine(line);
+		await this.#syncLines();
 	}
 
 	async removeLine(line: Line) {
-		const lines = await removeLine(line);
-		this.#setLines(lines);
+		if (line.id === undefined) {
+			return;
+		}
+		await removeLine(line.id);
+		await this.#syncLines();
 	}
 
 	async addEntity(entity: EntityStart) {
@@ -57,6 +81,25 @@ export class GameManager {
 		await this.setFrame(this.frame());
 	}
 
+	async #syncLines() {
+		const delta = await linesSince(this.#document, this.#revision);
+		this.#applyDelta(delta);
+	}
+
+	#applyDelta(delta: LineDelta) {
+		if (delta.reset) {
+			this.#setLines(delta.added);
+		} else {
+			const removed = new Set(delta.removed);
+			const kept = this.lines().filter(
+				(line) => line.id === undefined || !removed.has(line.id),
+			);
+			this.#setLines(kept.concat(delta.added));
+		}
+		this.#document = delta.document;
+		this.#revision = delta.revision;
+	}
+
 	#setFrame(frame: number) {
 		this.#frameSignal[1](frame);
 	}
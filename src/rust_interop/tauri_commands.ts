@@ -1,5 +1,24 @@
+import { listen, UnlistenFn } from '@tauri-apps/api/event';
 import { invoke } from '@tauri-apps/api/tauri';
-import { EntityStart, Line, RuntimeEntity, Track } from './tauri_types';
+import {
+	CompactFrames,
+	ConversionWarning,
+	DocumentInfo,
+	EntityStart,
+	FrameCacheStats,
+	HistorySummary,
+	Layer,
+	Line,
+	LineChanges,
+	LineContacts,
+	LineDelta,
+	LoadedTrack,
+	RiderEvent,
+	RuntimeEntity,
+	SimulationProgress,
+	TrackFormat,
+	TrackMeta,
+} from './tauri_types';
 
 export async function entityPositionsAt(
 	frame: number,
@@ -7,6 +26,60 @@ export async function entityPositionsAt(
 	return (await invoke('entity_positions_at', { frame })) as RuntimeEntity[];
 }
 
+export async function entityPositionsRange(
+	start: number,
+	end: number,
+	step = 1,
+): Promise<RuntimeEntity[][]> {
+	return await invoke('entity_positions_range', { start, end, step });
+}
+
+export async function compactPositionsRange(
+	start: number,
+	end: number,
+	step = 1,
+): Promise<CompactFrames> {
+	return await invoke('compact_positions_range', { start, end, step });
+}
+
+export async function findEvents(
+	start: number,
+	end: number,
+	screen?: [[number, number], [number, number]],
+): Promise<RiderEvent[]> {
+	return await invoke('find_events', { start, end, screen });
+}
+
+export async function lineContacts(
+	start: number,
+	end = start + 1,
+): Promise<LineContacts> {
+	return await invoke('line_contacts', { start, end });
+}
+
+export async function simulateAhead(
+	playhead: number,
+	frames: number,
+): Promise<void> {
+	await invoke('simulate_ahead', { playhead, frames });
+}
+
+export async function cancelSimulation(): Promise<void> {
+	await invoke('cancel_simulation', {});
+}
+
+export async function onSimulationProgress(
+	handler: (progress: SimulationProgress) => void,
+): Promise<UnlistenFn> {
+	return await listen<SimulationProgress>('simulated_up_to', (event) =>
+		handler(event.payload),
+	);
+}
+
+export async function frameCacheStats(): Promise<FrameCacheStats> {
+	return await invoke('frame_cache_stats', {});
+}
+
 export async function addEntity(entity: EntityStart): Promise<void> {
 	await invoke('add_entity', { entity });
 }
@@ -15,18 +88,149 @@ export async function removeEntity(entity: EntityStart): Promise<void> {
 	await invoke('remove_entity', { entity });
 }
 
-export async function addLine(line: Line): Promise<Line[]> {
-	return await invoke('add_line', { line });
+export async function addLine(
+	line: Line,
+	layer?: number,
+): Promise<LineChanges> {
+	return await invoke('add_line', { line, layer });
 }
 
-export async function removeLine(line: Line): Promise<Line[]> {
-	return await invoke('remove_line', { line });
+export async function removeLine(id: number): Promise<LineChanges> {
+	return await invoke('remove_line', { id });
 }
 
-export async function loadTrack(path: string): Promise<Track> {
+export async function addLines(
+	lines: Line[],
+	layer?: number,
+): Promise<LineChanges> {
+	return await invoke('add_lines', { lines, layer });
+}
+
+export async function removeLines(ids: number[]): Promise<LineChanges> {
+	return await invoke('remove_lines', { ids });
+}
+
+export async function replaceLines(lines: Line[]): Promise<LineChanges> {
+	return await invoke('replace_lines', { lines });
+}
+
+export async function linesSince(
+	document: number | undefined,
+	revision: number,
+): Promise<LineDelta> {
+	return await invoke('lines_since', { document, revision });
+}
+
+export async function linesInRect(
+	min: [number, number],
+	max: [number, number],
+): Promise<Line[]> {
+	return await invoke('lines_in_rect', { min, max });
+}
+
+export async function linesNearPoint(
+	point: [number, number],
+	radius: number,
+): Promise<Line[]> {
+	return await invoke('lines_near_point', { point, radius });
+}
+
+export async function loadTrack(path: string): Promise<LoadedTrack> {
 	return await invoke('load_track', { path });
 }
 
-export async function clear(): Promise<void> {
-	await invoke('clear', {});
+export async function saveTrack(
+	path: string,
+	format: TrackFormat,
+): Promise<ConversionWarning[]> {
+	return await invoke('save_track', { path, format });
+}
+
+export async function getTrackMeta(): Promise<TrackMeta> {
+	return await invoke('get_track_meta', {});
+}
+
+export async function setTrackMeta(meta: TrackMeta): Promise<void> {
+	await invoke('set_track_meta', { meta });
+}
+
+export async function getLayers(): Promise<Layer[]> {
+	return await invoke('get_layers', {});
+}
+
+export async function createLayer(
+	name: string,
+	folder?: number,
+): Promise<Layer> {
+	return await invoke('create_layer', { name, folder });
+}
+
+export async function renameLayer(id: number, name: string): Promise<Layer[]> {
+	return await invoke('rename_layer', { id, name });
+}
+
+export async function setLayerVisible(
+	id: number,
+	visible: boolean,
+): Promise<Layer[]> {
+	return await invoke('set_layer_visible', { id, visible });
+}
+
+export async function setLayerEditable(
+	id: number,
+	editable: boolean,
+): Promise<Layer[]> {
+	return await invoke('set_layer_editable', { id, editable });
+}
+
+export async function moveLayer(id: number, index: number): Promise<Layer[]> {
+	return await invoke('move_layer', { id, index });
+}
+
+export async function undo(): Promise<LineChanges> {
+	return await invoke('undo', {});
+}
+
+export async function redo(): Promise<LineChanges> {
+	return await invoke('redo', {});
+}
+
+export async function history(): Promise<HistorySummary> {
+	return await invoke('history', {});
+}
+
+export async function beginEditGroup(label: string): Promise<void> {
+	await invoke('begin_edit_group', { label });
+}
+
+export async function endEditGroup(): Promise<void> {
+	await invoke('end_edit_group', {});
+}
+
+export async function clear(): Promise<LineChanges> {
+	return await invoke('clear', {});
+}
+
+export async function openDocument(): Promise<number> {
+	return await invoke('open_document', {});
+}
+
+export async function selectDocument(id: number): Promise<void> {
+	await invoke('select_document', { id });
+}
+
+export async function closeDocument(id: number): Promise<void> {
+	await invoke('close_document', { id });
+}
+
+export async function listDocuments(): Promise<DocumentInfo[]> {
+	return await invoke('list_documents', {});
+}
+
+export async function copyLines(
+	from: number,
+	ids: number[],
+	layer?: number,
+): Promise<LineChanges> {
+	return await invoke('copy_lines', { from, ids, layer });
 }
@@ -33,6 +33,8 @@ export type EntityStart =
 			boshSled: {
 				position: [number, number];
 				velocity?: [number, number];
+				remountable?: boolean;
+				frictionless?: boolean;
 			};
 	  }
 	| {
@@ -45,13 +47,149 @@ export type LinePoint = {
 };
 
 export type Line = {
+	id?: number;
+	width?: number;
+	color?: Color;
+	layer?: number;
 	flipped: boolean;
 	lineType: 'Normal' | { Accelerate: { accel: number } } | 'Scenery';
 	ends: [LinePoint, LinePoint];
 };
 
+export type Trigger = {
+	zoom: { line: number; target: number; frames: number };
+};
+
+export type Color = { r: number; g: number; b: number };
+
+export type TrackMeta = {
+	title?: string;
+	author?: string;
+	description?: string;
+	song?: { name: string; offsetSeconds: number };
+	duration?: number;
+	startZoom?: number;
+	gravityScale?: [number, number];
+	gravityWellSize?: number;
+	backgroundColor?: Color;
+	lineColor?: Color;
+	lraEntries?: Record<string, string>;
+	[physicsKey: string]: any;
+};
+
+export type Layer = {
+	id: number;
+	name: string;
+	visible: boolean;
+	editable: boolean;
+	folder?: number;
+	isFolder?: boolean;
+};
+
 export type Track = {
-	meta?: Record<string, any>; // todo strong types
+	meta?: TrackMeta;
 	lines: Line[];
 	entities: EntityStart[];
+	triggers?: Trigger[];
+	layers?: Layer[];
+};
+
+export type TrackFormat = 'boshtf' | 'track.json' | 'trk';
+
+export type Detection = {
+	format: TrackFormat;
+	detector: 'trkMagic' | 'lrcomKeys' | 'boshtfKeys';
+	extensionHint?: TrackFormat;
+};
+
+export type LoadedTrack = Track & {
+	detection: Detection;
+	document: number;
+	revision: number;
+};
+
+export type ConversionWarning =
+	| { kind: 'entityDropped'; entityIndex: number; reason: string }
+	| { kind: 'entityAltered'; entityIndex: number; reason: string }
+	| { kind: 'lineAltered'; lineIndex: number; reason: string }
+	| { kind: 'metaDropped'; field: string; reason: string }
+	| { kind: 'triggerDropped'; triggerIndex: number; reason: string };
+
+export type HistorySummary = {
+	undo: string[];
+	redo: string[];
+	group?: string;
+	bytesUsed: number;
+	byteBudget: number;
+};
+
+export type LineChanges = {
+	revision: number;
+	reset: boolean;
+	added: number[];
+	removed: number[];
+};
+
+export type LineDelta = {
+	document: number;
+	revision: number;
+	reset: boolean;
+	removed: number[];
+	added: Line[];
+};
+
+export type FrameCacheStats = {
+	cachedFrames: number;
+	hits: number;
+	misses: number;
+	invalidations: number;
+	lastInvalidatedFrom?: number;
+};
+
+export type CompactFrames = {
+	frames: number[];
+	points: string[][];
+	// missing points are packed as null
+	locations: (number | null)[];
+};
+
+export type RiderEventKind =
+	| 'Dismounted'
+	| 'SledBroken'
+	| 'FirstContact'
+	| 'LeftScreen';
+
+export type RiderEvent = {
+	frame: number;
+	entity: number;
+	kind: RiderEventKind;
+};
+
+export type PointContact = {
+	entity: number;
+	point: string;
+	lines: number[];
+};
+
+export type FrameContacts = {
+	frame: number;
+	contacts: PointContact[];
+};
+
+export type LineContacts = {
+	frames: FrameContacts[];
+	hitLines: number[];
+};
+
+export type SimulationProgress = {
+	document: number;
+	revision: number;
+	simulatedUpTo: number;
+	target: number;
+};
+
+export type DocumentInfo = {
+	id: number;
+	window: string;
+	active: boolean;
 };